@@ -0,0 +1,37 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#![no_std]
+
+extern crate bacon_rajan_cc;
+
+use core::cell::RefCell;
+
+use bacon_rajan_cc::{collect_cycles, Cc, CcTrace, Tracer};
+
+struct Node {
+    next: RefCell<Option<Cc<Node>>>,
+}
+
+impl CcTrace for Node {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.next.trace(tracer);
+    }
+}
+
+/// Build a garbage cycle and collect it, returning the number of boxes freed.
+pub fn smoke() -> usize {
+    let a = Cc::new(Node { next: RefCell::new(None) });
+    let b = Cc::new(Node { next: RefCell::new(Some(a.clone())) });
+    *a.next.borrow_mut() = Some(b.clone());
+    drop(a);
+    drop(b);
+    collect_cycles().objects_freed
+}
@@ -0,0 +1,651 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The buffer of possible cycle roots and the collector that drains it.
+//!
+//! This is the synchronous cycle collection algorithm described in "Concurrent
+//! Cycle Collection in Reference Counted Systems" by David F. Bacon and V.T.
+//! Rajan.
+
+use core::alloc::Allocator;
+use core::cell::{Cell, RefCell};
+use core::cmp;
+use core::fmt::Write;
+use core::mem;
+
+use alloc::boxed::Box;
+use collections::btree_set::BTreeSet;
+use collections::string::String;
+use collections::vec::Vec;
+
+use super::{is_empty_weak, Cc, CcBoxMetadata, CcBoxPtr};
+use super::box_ptr::{CcBoxCounts, CcBoxHooks};
+use color::Color;
+
+// The boxes whose strong count was decremented to a non-zero value since the
+// last collection. The buffer does not own any reference counts, so a box that
+// dies while it is buffered is kept allocated until the collector drains it.
+cc_thread_local!(static ROOTS: RefCell<Roots> = RefCell::new(Roots(Vec::new())));
+
+struct Roots(Vec<Box<CcBoxPtr>>);
+
+/// The default number of buffered roots above which a collection is
+/// automatically triggered.
+pub const DEFAULT_AUTO_COLLECT_THRESHOLD: usize = 256;
+
+// The number of buffered roots above which a collection is automatically
+// triggered, or `None` if that's disabled.
+cc_thread_local!(static AUTO_COLLECT_THRESHOLD: Cell<Option<usize>> =
+                 Cell::new(Some(DEFAULT_AUTO_COLLECT_THRESHOLD)));
+
+// The possible roots found while collection is paused by
+// `with_collection_paused`, or `None` if it isn't. They are marked as buffered
+// just like the ones in `ROOTS`, and are moved there once the pause is over.
+cc_thread_local!(static PAUSED_ROOTS: RefCell<Option<Vec<Box<CcBoxPtr>>>> = RefCell::new(None));
+
+impl Drop for Roots {
+    // The thread is going away, so no collection is ever going to drain the
+    // buffer. Free the boxes that were only being kept around for it, and let
+    // the rest be freed by their owners.
+    fn drop(&mut self) {
+        for s in &self.0 {
+            s.metadata().buffered.set(false);
+            if s.strong() == 0 && s.weak() == 0 {
+                unsafe { s.deallocate(); }
+            }
+        }
+    }
+}
+
+/// Called when the strong count of the given box was decremented, but did not
+/// reach zero. The box might now be the root of a garbage cycle, so paint it
+/// purple and remember it for the next collection.
+///
+/// A box is buffered at most once, no matter how many times it is decremented
+/// before the next collection. With the `no-cycle-collection` feature, nothing
+/// is ever buffered.
+pub fn possible_root(box_ptr: &CcBoxPtr) {
+    if cfg!(feature = "no-cycle-collection") {
+        return;
+    }
+
+    if box_ptr.color() == Color::Purple {
+        return;
+    }
+
+    box_ptr.metadata().color.set(Color::Purple);
+    if !box_ptr.buffered() {
+        box_ptr.metadata().buffered.set(true);
+        let root = box_ptr.box_ref();
+        let root = PAUSED_ROOTS.with(|p| {
+            match *p.borrow_mut() {
+                Some(ref mut paused) => {
+                    paused.push(root);
+                    None
+                }
+                None => Some(root),
+            }
+        });
+        if let Some(root) = root {
+            let buffered = ROOTS.with(|r| {
+                let mut roots = r.borrow_mut();
+                roots.0.push(root);
+                roots.0.len()
+            });
+            maybe_auto_collect(buffered);
+        }
+    }
+}
+
+fn maybe_auto_collect(buffered: usize) {
+    let threshold = AUTO_COLLECT_THRESHOLD.with(|t| t.get());
+    if threshold.map_or(false, |t| buffered > t) {
+        collect_cycles();
+    }
+}
+
+/// Call `f` without buffering possible roots or automatically collecting
+/// cycles while it runs.
+///
+/// This is meant for building large graphs, where every new box would
+/// otherwise be buffered, and likely collected, while the graph is only half
+/// built. The boxes that would have been buffered are remembered, and once `f`
+/// returns, the ones that are still possible roots are added to the buffer at
+/// once, which can trigger a single automatic collection. Boxes that were
+/// incremented again or freed in the meantime are left out.
+///
+/// Calling `collect_cycles` from within `f` still works, but only looks at
+/// the roots buffered before the pause. Nested calls just run `f`, and leave
+/// the reconciliation to the outermost one.
+///
+/// ```
+/// use bacon_rajan_cc::{with_collection_paused, Cc};
+///
+/// let nodes: Vec<Cc<u32>> = with_collection_paused(|| {
+///     (0..100).map(Cc::new).collect()
+/// });
+/// assert_eq!(nodes.len(), 100);
+/// ```
+pub fn with_collection_paused<R, F: FnOnce() -> R>(f: F) -> R {
+    let outermost = PAUSED_ROOTS.with(|p| {
+        let mut paused = p.borrow_mut();
+        let outermost = paused.is_none();
+        if outermost {
+            *paused = Some(Vec::new());
+        }
+        outermost
+    });
+    if !outermost {
+        return f();
+    }
+
+    let _guard = ResumeCollection;
+    f()
+}
+
+// Ends the pause started by the outermost `with_collection_paused`, even if
+// the closure panicked.
+struct ResumeCollection;
+
+impl Drop for ResumeCollection {
+    fn drop(&mut self) {
+        let paused = PAUSED_ROOTS.with(|p| p.borrow_mut().take().unwrap());
+        let mut buffered = 0;
+        ROOTS.with(|r| {
+            let mut roots = r.borrow_mut();
+            for s in paused {
+                if s.color() == Color::Purple && s.strong() > 0 {
+                    roots.0.push(s);
+                } else {
+                    // the same as what `mark_roots` would do with it.
+                    s.metadata().buffered.set(false);
+                    if s.strong() == 0 && s.weak() == 0 {
+                        unsafe { s.deallocate(); }
+                    }
+                }
+            }
+            buffered = roots.0.len();
+        });
+        maybe_auto_collect(buffered);
+    }
+}
+
+/// Automatically collect cycles whenever more than `n` possible roots are
+/// buffered on this thread.
+///
+/// This only takes effect on the current thread. The default threshold is
+/// `DEFAULT_AUTO_COLLECT_THRESHOLD`.
+///
+/// Automatic collections only ever happen at a safe point: when a `Cc` is
+/// dropped and its box is buffered as a possible root. They never happen in the
+/// middle of tracing a value, but they can run destructors of garbage values
+/// from within the `Drop` of an unrelated `Cc`.
+pub fn set_auto_collect_threshold(n: usize) {
+    AUTO_COLLECT_THRESHOLD.with(|t| t.set(Some(n)));
+}
+
+/// Never automatically collect cycles on this thread. Cycles are then only
+/// collected by calling `collect_cycles`.
+pub fn disable_auto_collect() {
+    AUTO_COLLECT_THRESHOLD.with(|t| t.set(None));
+}
+
+/// How much work a call to `collect_cycles` did.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CollectStats {
+    /// The number of buffered possible roots that were looked at.
+    pub roots_scanned: usize,
+    /// The number of garbage cycles that were freed. Garbage that can be
+    /// reached from several roots is only counted for the first of them.
+    pub cycles_freed: usize,
+    /// The number of boxes that were freed, across all the garbage cycles.
+    pub objects_freed: usize,
+}
+
+/// Look for garbage cycles among the possible roots buffered since the last
+/// collection, and free them.
+///
+/// Calling this while a collection is already running on this thread, from
+/// the `Drop` of a value it is freeing, does nothing and returns empty stats.
+/// Anything buffered in the meantime is left for the next collection.
+///
+/// With the `no-cycle-collection` feature, this does nothing and returns empty
+/// stats, and garbage cycles are leaked.
+pub fn collect_cycles() -> CollectStats {
+    if cfg!(feature = "no-cycle-collection") {
+        return CollectStats::default();
+    }
+    let _guard = match Collecting::start() {
+        Some(guard) => guard,
+        None => return CollectStats::default(),
+    };
+    let roots = ROOTS.with(|r| mem::replace(&mut r.borrow_mut().0, Vec::new()));
+    collect(roots)
+}
+
+/// A guard that collects cycles when it goes out of scope.
+///
+/// ```
+/// use bacon_rajan_cc::CollectOnDrop;
+///
+/// {
+///     let _guard = CollectOnDrop;
+///     // any cycles made garbage in this scope are freed at its end.
+/// }
+/// ```
+#[derive(Debug)]
+pub struct CollectOnDrop;
+
+impl Drop for CollectOnDrop {
+    fn drop(&mut self) {
+        collect_cycles();
+    }
+}
+
+/// Like `collect_cycles`, but only look at the `max_roots` possible roots that
+/// were buffered first, and leave the rest for a later collection.
+///
+/// This bounds the number of roots a collection starts from, though not the
+/// size of the graph reachable from them. The roots that are left buffered are
+/// unaffected: any of them that turns out to be garbage reachable from the
+/// roots that were looked at is freed now, and the rest are looked at by a
+/// later collection.
+pub fn collect_cycles_with_budget(max_roots: usize) -> CollectStats {
+    if cfg!(feature = "no-cycle-collection") {
+        return CollectStats::default();
+    }
+    let _guard = match Collecting::start() {
+        Some(guard) => guard,
+        None => return CollectStats::default(),
+    };
+    let roots = ROOTS.with(|r| {
+        let mut roots = r.borrow_mut();
+        let n = cmp::min(max_roots, roots.0.len());
+        let rest = roots.0.split_off(n);
+        mem::replace(&mut roots.0, rest)
+    });
+    collect(roots)
+}
+
+/// Look for garbage cycles among the boxes reachable from `roots`, and free
+/// them, whether or not any of those boxes are buffered as possible roots.
+///
+/// This is meant for tearing down a subsystem whose boxes are known to be
+/// likely garbage, without waiting for, or paying for, a collection of all
+/// the buffered roots. Because a `Cc` keeps its box alive, the roots are
+/// usually `Weak`s to boxes that are no longer owned from outside.
+///
+/// The roots buffer is left alone: any of its boxes that are freed here stay
+/// buffered until a later collection drains them. Rooted boxes are live, and
+/// are not collected from. Like `collect_cycles`, this does nothing if a
+/// collection is already running on this thread.
+///
+/// ```
+/// use std::cell::RefCell;
+/// use bacon_rajan_cc::{collect_from, Cc, CcBoxPtr, CcTrace, Tracer};
+///
+/// struct Node(RefCell<Option<Cc<Node>>>);
+///
+/// impl CcTrace for Node {
+///     fn trace(&self, tracer: &mut Tracer) {
+///         if let Some(ref next) = *self.0.borrow() {
+///             tracer(next);
+///         }
+///     }
+/// }
+///
+/// let a = Cc::new(Node(RefCell::new(None)));
+/// *a.0.borrow_mut() = Some(a.clone());
+/// let weak = a.downgrade();
+/// drop(a);
+///
+/// let stats = collect_from(&[&weak as &CcBoxPtr]);
+/// assert_eq!(stats.objects_freed, 1);
+/// assert!(weak.upgrade().is_none());
+/// ```
+pub fn collect_from(roots: &[&CcBoxPtr]) -> CollectStats {
+    if cfg!(feature = "no-cycle-collection") {
+        return CollectStats::default();
+    }
+    let _guard = match Collecting::start() {
+        Some(guard) => guard,
+        None => return CollectStats::default(),
+    };
+    let mut stats = CollectStats { roots_scanned: roots.len(), ..CollectStats::default() };
+    let mut marked = Vec::new();
+    for s in roots {
+        if s.strong() > 0 && !s.rooted() {
+            mark_gray(*s);
+            marked.push(s.box_ref());
+        }
+    }
+    scan_roots(&marked);
+    free_white(&marked, &mut stats);
+    stats
+}
+
+// Whether a collection is running on this thread. The colors of the boxes are
+// only meaningful to the collection that painted them, so another one must not
+// start until it is done.
+cc_thread_local!(static COLLECTING: Cell<bool> = Cell::new(false));
+
+// Marks a collection as running for as long as it is alive, even if dropping a
+// value panics.
+struct Collecting;
+
+impl Collecting {
+    fn start() -> Option<Collecting> {
+        COLLECTING.with(|c| {
+            if c.get() {
+                None
+            } else {
+                c.set(true);
+                Some(Collecting)
+            }
+        })
+    }
+}
+
+impl Drop for Collecting {
+    fn drop(&mut self) {
+        COLLECTING.with(|c| c.set(false));
+    }
+}
+
+fn collect(roots: Vec<Box<CcBoxPtr>>) -> CollectStats {
+    let mut stats = CollectStats { roots_scanned: roots.len(), ..CollectStats::default() };
+    let roots = mark_roots(roots);
+    scan_roots(&roots);
+    collect_roots(roots, &mut stats);
+    stats
+}
+
+// Mark everything reachable from the buffered roots gray, subtracting the
+// internal references from the counts as we go. Roots that are no longer purple
+// were incremented since they were buffered, and roots whose strong count went
+// to zero already had their values dropped by the normal path. Both are dropped
+// from the buffer, along with rooted boxes, which are known to be live, and a
+// dead root is freed here if nothing else is keeping its allocation around.
+fn mark_roots(roots: Vec<Box<CcBoxPtr>>) -> Vec<Box<CcBoxPtr>> {
+    let mut marked = Vec::new();
+    for s in roots {
+        if s.color() == Color::Purple && s.strong() > 0 && !s.rooted() {
+            mark_gray(&*s);
+            marked.push(s);
+        } else {
+            s.metadata().buffered.set(false);
+            if s.strong() == 0 && s.weak() == 0 {
+                // the value was dropped while the box was buffered, and we were
+                // the last thing keeping the allocation around.
+                unsafe { s.deallocate(); }
+            }
+        }
+    }
+    marked
+}
+
+// Like the other phases, this walks the graph with a stack of its own rather
+// than by recursing, so that a long chain can't overflow the thread's stack.
+fn mark_gray(s: &CcBoxPtr) {
+    if s.color() == Color::Gray {
+        return;
+    }
+    s.metadata().color.set(Color::Gray);
+    let mut stack = Vec::new();
+    stack.push(s.box_ref());
+    while let Some(s) = stack.pop() {
+        for_each_child(&*s, &mut |t| {
+            t.metadata().strong.set(t.strong() - 1);
+            if t.color() != Color::Gray {
+                t.metadata().color.set(Color::Gray);
+                stack.push(t.box_ref());
+            }
+        });
+    }
+}
+
+// Anything gray that still has a reference from outside of the graph that
+// was marked, or that is rooted, is live, and so is everything reachable from
+// it. Everything else is garbage, and painted white.
+fn scan_roots(roots: &[Box<CcBoxPtr>]) {
+    for s in roots {
+        scan(&**s);
+    }
+}
+
+fn scan(s: &CcBoxPtr) {
+    let mut stack = Vec::new();
+    stack.push(s.box_ref());
+    while let Some(s) = stack.pop() {
+        if s.color() == Color::Gray {
+            if s.strong() > 0 || s.rooted() {
+                scan_black(&*s);
+            } else {
+                s.metadata().color.set(Color::White);
+                for_each_child(&*s, &mut |t| {
+                    if t.color() == Color::Gray {
+                        stack.push(t.box_ref());
+                    }
+                });
+            }
+        }
+    }
+}
+
+fn scan_black(s: &CcBoxPtr) {
+    s.metadata().color.set(Color::Black);
+    let mut stack = Vec::new();
+    stack.push(s.box_ref());
+    while let Some(s) = stack.pop() {
+        for_each_child(&*s, &mut |t| {
+            t.metadata().strong.set(t.strong() + 1);
+            if t.color() != Color::Black {
+                t.metadata().color.set(Color::Black);
+                stack.push(t.box_ref());
+            }
+        });
+    }
+}
+
+// Take the roots out of the buffer, and free every white box.
+fn collect_roots(roots: Vec<Box<CcBoxPtr>>, stats: &mut CollectStats) {
+    for s in &roots {
+        s.metadata().buffered.set(false);
+    }
+    free_white(&roots, stats);
+}
+
+// Free every white box reachable from the roots. The boxes that are still
+// buffered are left allocated, for whatever buffer they are in to drain.
+fn free_white(roots: &[Box<CcBoxPtr>], stats: &mut CollectStats) {
+    let mut white = Vec::new();
+    for s in roots {
+        let before = white.len();
+        collect_white(&**s, &mut white);
+        if white.len() > before {
+            stats.cycles_freed += 1;
+        }
+    }
+    stats.objects_freed = white.len();
+
+    // The references between white boxes were subtracted from their counts
+    // while marking, but dropping the values is going to decrement the counts
+    // of their children again. Add them back, so that every count ends up at
+    // zero once all the values are gone.
+    for s in &white {
+        s.metadata().color.set(Color::White);
+    }
+    for s in &white {
+        for_each_child(&**s, &mut |t| {
+            t.metadata().strong.set(t.strong() + 1);
+        });
+    }
+
+    // While a box is white, dropping a `Cc` pointing at it only decrements its
+    // count, so every value here is dropped exactly once, by us.
+    for s in &white {
+        unsafe { s.drop_value(); }
+    }
+
+    for s in &white {
+        debug_assert!(s.strong() == 0);
+        s.metadata().color.set(Color::Black);
+        // remove the implicit "strong weak" pointer now that we've destroyed
+        // the contents.
+        s.dec_weak();
+        if s.weak() == 0 && !s.buffered() {
+            unsafe { s.deallocate(); }
+        }
+    }
+}
+
+fn collect_white(s: &CcBoxPtr, white: &mut Vec<Box<CcBoxPtr>>) {
+    if s.color() != Color::White {
+        return;
+    }
+    s.metadata().color.set(Color::Black);
+    let mut stack = Vec::new();
+    stack.push(s.box_ref());
+    while let Some(s) = stack.pop() {
+        for_each_child(&*s, &mut |t| {
+            if t.color() == Color::White {
+                t.metadata().color.set(Color::Black);
+                stack.push(t.box_ref());
+            }
+        });
+        white.push(s);
+    }
+}
+
+/// A snapshot of a box in the roots buffer, as returned by `dump_roots`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RootInfo {
+    /// The address of the box, which is what formatting a `Cc` pointing to it
+    /// with `{:p}` prints.
+    pub address: usize,
+    /// The number of `Cc`s pointing to the box.
+    pub strong: usize,
+    /// The number of `Weak`s pointing to the box.
+    pub weak: usize,
+    /// The box's color.
+    pub color: Color,
+}
+
+/// Describe each box currently buffered as a possible root of a garbage cycle
+/// on this thread, in the order they were buffered.
+///
+/// This is meant for debugging, and doesn't change the buffer or the boxes. A
+/// box that died while it was buffered is still listed, with a strong count of
+/// zero.
+pub fn dump_roots() -> Vec<RootInfo> {
+    ROOTS.with(|r| {
+        r.borrow().0.iter().map(|s| {
+            RootInfo {
+                address: address(&**s),
+                strong: s.strong(),
+                weak: weak_count(&**s),
+                color: s.color(),
+            }
+        }).collect()
+    })
+}
+
+/// Describe the boxes reachable from `root` and the edges between them, in
+/// Graphviz's DOT language.
+///
+/// There is a node for every box, labeled with its address and its counts,
+/// and an edge for every `Cc` found by tracing its value. Every box is only
+/// visited once, so cycles are fine. Like `dump_roots`, this is meant for
+/// debugging, and doesn't change anything.
+///
+/// ```
+/// use bacon_rajan_cc::{dump_graph_dot, Cc};
+///
+/// let five = Cc::new(5);
+/// let dot = dump_graph_dot(&five);
+/// assert!(dot.starts_with("digraph cc {"));
+/// ```
+pub fn dump_graph_dot<T: 'static + super::CcTrace + ?Sized, A: 'static + Allocator>(root: &Cc<T, A>) -> String {
+    let mut out = String::from("digraph cc {\n");
+    let mut visited = BTreeSet::new();
+    let mut stack = Vec::new();
+    stack.push(root.box_ref());
+    visited.insert(address(root));
+    while let Some(s) = stack.pop() {
+        let from = address(&*s);
+        let _ = write!(out, "    \"{:#x}\" [label=\"{:#x}\\nstrong: {}, weak: {}\"];\n",
+                       from, from, s.strong(), weak_count(&*s));
+        for_each_child(&*s, &mut |t| {
+            let to = address(t);
+            let _ = write!(out, "    \"{:#x}\" -> \"{:#x}\";\n", from, to);
+            if visited.insert(to) {
+                stack.push(t.box_ref());
+            }
+        });
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// Returns the addresses of the boxes `value` owns, in the order it traces
+/// them, by calling its `trace` with a tracer that records them.
+///
+/// This is a building block for walking a graph of `Cc`s outside of the
+/// collector. The addresses are what formatting a `Cc` with `{:p}` prints, and
+/// a box owned through several `Cc`s is listed once for each.
+///
+/// ```
+/// use bacon_rajan_cc::{trace_children, Cc};
+///
+/// let child = Cc::new(5);
+/// let children = trace_children(&vec![child.clone(), Cc::new(6)]);
+/// assert_eq!(children.len(), 2);
+/// assert_eq!(format!("{:#x}", children[0]), format!("{:p}", child));
+/// ```
+pub fn trace_children<T: super::CcTrace + ?Sized>(value: &T) -> Vec<usize> {
+    let mut children = Vec::new();
+    value.trace(&mut |t| {
+        if !is_empty_weak(t) {
+            children.push(address(t));
+        }
+    });
+    children
+}
+
+// Call `f` with each box the value in `s` owns. A `trace` impl may hand its
+// tracer an empty `Weak`, which has no box, so those are left out.
+fn for_each_child(s: &CcBoxPtr, f: &mut FnMut(&CcBoxPtr)) {
+    s.trace(&mut |t| {
+        if !is_empty_weak(t) {
+            f(t);
+        }
+    });
+}
+
+// The address of a box, which is what formatting a `Cc` pointing to it with
+// `{:p}` prints.
+fn address(s: &CcBoxPtr) -> usize {
+    s.metadata() as *const CcBoxMetadata as usize
+}
+
+// The number of `Weak`s pointing to a box. While there are strong references,
+// they share one implicit weak reference between them, which isn't counted.
+// It is only given up once the value is dropped, which is while the box is
+// white.
+fn weak_count(s: &CcBoxPtr) -> usize {
+    let implicit_weak = if s.strong() > 0 || s.color() == Color::White { 1 } else { 0 };
+    s.weak() - implicit_weak
+}
+
+/// Get the number of boxes currently in the roots buffer.
+#[cfg(test)]
+pub fn number_of_roots_buffered() -> usize {
+    ROOTS.with(|r| r.borrow().0.len())
+}
@@ -0,0 +1,32 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The colors used to mark `CcBox`es during trial deletion.
+//!
+//! These follow the synchronous cycle collection algorithm described in
+//! "Concurrent Cycle Collection in Reference Counted Systems" by David F. Bacon
+//! and V.T. Rajan.
+
+/// The color of a `CcBox`, used by the cycle collector to keep track of which
+/// boxes it has visited and what it has decided about them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Color {
+    /// In use or free.
+    Black,
+
+    /// Possible member of a cycle.
+    Gray,
+
+    /// Member of a garbage cycle.
+    White,
+
+    /// Possible root of a cycle.
+    Purple,
+}
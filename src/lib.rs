@@ -32,19 +32,29 @@
 //!
 //! ```rust
 //! # #![feature(alloc, collections)]
-//! use bacon_rajan_cc::Cc;
+//! use bacon_rajan_cc::{Cc, CcTrace, Tracer};
 //!
 //! struct Owner {
 //!     name: String
 //!     // ...other fields
 //! }
 //!
+//! impl CcTrace for Owner {
+//!     fn trace(&self, _tracer: &mut Tracer) { }
+//! }
+//!
 //! struct Gadget {
 //!     id: i32,
 //!     owner: Cc<Owner>
 //!     // ...other fields
 //! }
 //!
+//! impl CcTrace for Gadget {
+//!     fn trace(&self, tracer: &mut Tracer) {
+//!         tracer(&self.owner);
+//!     }
+//! }
+//!
 //! fn main() {
 //!     // Create a reference counted Owner.
 //!     let gadget_owner : Cc<Owner> = Cc::new(
@@ -92,7 +102,7 @@
 //!
 //! ```rust
 //! # #![feature(alloc)]
-//! use bacon_rajan_cc::Cc;
+//! use bacon_rajan_cc::{Cc, CcTrace, Tracer};
 //! use bacon_rajan_cc::Weak;
 //! use std::cell::RefCell;
 //!
@@ -102,12 +112,24 @@
 //!     // ...other fields
 //! }
 //!
+//! impl CcTrace for Owner {
+//!     // `Weak<T>` pointers don't own the `Gadget`s, so there is nothing to
+//!     // trace.
+//!     fn trace(&self, _tracer: &mut Tracer) { }
+//! }
+//!
 //! struct Gadget {
 //!     id: i32,
 //!     owner: Cc<Owner>
 //!     // ...other fields
 //! }
 //!
+//! impl CcTrace for Gadget {
+//!     fn trace(&self, tracer: &mut Tracer) {
+//!         tracer(&self.owner);
+//!     }
+//! }
+//!
 //! fn main() {
 //!     // Create a reference counted Owner. Note the fact that we've put the
 //!     // Owner's vector of Gadgets inside a RefCell so that we can mutate it
@@ -147,65 +169,142 @@
 //! }
 //! ```
 
+#![cfg_attr(not(feature = "std"), no_std)]
+
 #![feature(alloc)]
+#![feature(allocator_api)]
+#![feature(coerce_unsized)]
+#![feature(collections)]
+#![feature(const_fn)]
 #![feature(core)]
 #![feature(custom_derive)]
-#![feature(filling_drop)]
 #![feature(plugin)]
 #![feature(plugin_registrar)]
 #![feature(quote)]
 #![feature(rustc_private)]
 #![feature(trace_macros)]
-#![feature(unsafe_no_drop_flag)]
+#![feature(unsize)]
 
+#[cfg(feature = "std")]
 #[macro_use]
 extern crate syntax;
+#[cfg(feature = "std")]
 #[macro_use]
 extern crate rustc;
 
-use std::boxed;
+#[cfg(feature = "std")]
+use std::error::Error;
+#[cfg(feature = "std")]
+use std::io;
 
+// `no_std` already links to `core`.
+#[cfg(feature = "std")]
 extern crate core;
-use core::cell::Cell;
+use core::alloc::{Allocator, Layout};
+use core::any::Any;
+use core::cell::{Cell, RefCell};
 use core::clone::Clone;
-use core::cmp::{PartialEq, PartialOrd, Eq, Ord, Ordering};
+use core::cmp::{self, PartialEq, PartialOrd, Eq, Ord, Ordering};
+use core::convert::{From, TryFrom};
 use core::default::Default;
 use core::fmt;
 use core::hash::{Hasher, Hash};
-use core::mem::{self, min_align_of, size_of, forget};
+use core::iter::{FromIterator, IntoIterator, Product, Sum};
+use core::mem::{self, min_align_of, size_of, forget, MaybeUninit};
 use core::nonzero::NonZero;
-use core::ops::{Deref, Drop};
+use core::marker::{Unpin, Unsize};
+use core::ops::{CoerceUnsized, Deref, Drop};
+use core::pin::Pin;
 use core::option::Option;
 use core::option::Option::{Some, None};
-use core::ptr;
+use core::ptr::{self, NonNull};
+use core::slice;
 use core::result::Result;
 use core::result::Result::{Ok, Err};
-use core::intrinsics::assume;
+use core::intrinsics::{assume, drop_in_place};
+use core::intrinsics::abort;
 
 extern crate alloc;
-use alloc::heap::deallocate;
+use alloc::boxed::{self, Box};
+use alloc::alloc::{handle_alloc_error, Global};
+
+extern crate collections;
+use collections::string::String;
+use collections::vec::Vec;
+
+#[cfg(feature = "serde")]
+extern crate serde;
 
 /// TODO FITZGEN
+#[cfg(feature = "std")]
 pub mod trace_plugin;
+#[cfg(feature = "std")]
 pub use trace_plugin::*;
 
-struct CcBox<T> {
-    value: T,
-    strong: Cell<usize>,
-    weak: Cell<usize>
+// Declares per-thread state. Without `std` there are no thread locals, so the
+// state is kept in statics instead, which the `unsafe-single-threaded` feature
+// promises are only ever used from one thread.
+#[cfg(feature = "std")]
+macro_rules! cc_thread_local {
+    (static $name:ident: $t:ty = $init:expr) => (
+        thread_local!(static $name: $t = $init);
+    )
+}
+
+#[cfg(not(feature = "std"))]
+macro_rules! cc_thread_local {
+    (static $name:ident: $t:ty = $init:expr) => (
+        static $name: ::local::Local<$t> = ::local::Local::new($init);
+    )
+}
+
+#[cfg(not(feature = "std"))]
+mod local;
+
+#[cfg(all(not(feature = "std"), not(feature = "unsafe-single-threaded")))]
+compile_error!("building without `std` requires the `unsafe-single-threaded` feature, \
+                which promises that the crate is only used from one thread");
+
+mod color;
+pub use color::Color;
+
+mod collect;
+pub use collect::{collect_cycles, set_auto_collect_threshold, disable_auto_collect};
+pub use collect::{collect_cycles_with_budget, collect_from, CollectStats};
+pub use collect::{with_collection_paused, CollectOnDrop};
+pub use collect::{dump_graph_dot, dump_roots, trace_children, RootInfo};
+pub use collect::DEFAULT_AUTO_COLLECT_THRESHOLD;
+
+mod trace;
+pub use trace::{register_trace_fn, CcTrace, Tracer};
+
+use box_ptr::{CcBoxCounts, CcBoxHooks};
+
+#[cfg(feature = "std")]
+pub mod weak_map;
+
+#[cfg(feature = "serde")]
+mod serde_impls;
+
+// The metadata comes first and the value last, which the layout computations
+// below rely on.
+#[repr(C)]
+struct CcBox<T: 'static + CcTrace + ?Sized, A: 'static + Allocator = Global> {
+    metadata: CcBoxMetadata,
+    alloc: A,
+    value: T
 }
 
 /// A reference-counted pointer type over an immutable value.
 ///
 /// See the [module level documentation](./) for more details.
-#[unsafe_no_drop_flag]
-pub struct Cc<T> {
+pub struct Cc<T: 'static + CcTrace + ?Sized, A: 'static + Allocator = Global> {
     // FIXME #12808: strange names to try to avoid interfering with field
     // accesses of the contained type via Deref
-    _ptr: NonZero<*mut CcBox<T>>,
+    _ptr: NonZero<*mut CcBox<T, A>>,
 }
 
-impl<T> Cc<T> {
+impl<T: 'static + CcTrace> Cc<T> {
     /// Constructs a new `Cc<T>`.
     ///
     /// # Examples
@@ -223,269 +322,170 @@ impl<T> Cc<T> {
                 // the allocation while the strong destructor is running, even
                 // if the weak pointer is stored inside the strong one.
                 _ptr: NonZero::new(boxed::into_raw(Box::new(CcBox {
-                    value: value,
-                    strong: Cell::new(1),
-                    weak: Cell::new(1)
+                    metadata: CcBoxMetadata::new(),
+                    alloc: Global,
+                    value: value
                 }))),
             }
         }
     }
 
-    /// Downgrades the `Cc<T>` to a `Weak<T>` reference.
+    /// Constructs a new `Cc<T>`, along with a `Weak<T>` to it.
     ///
     /// # Examples
     ///
     /// ```
-    /// # #![feature(alloc)]
     /// use bacon_rajan_cc::Cc;
     ///
-    /// let five = Cc::new(5);
-    ///
-    /// let weak_five = five.downgrade();
+    /// let (five, weak_five) = Cc::new_with_weak(5);
+    /// assert!(Cc::ptr_eq(&five, &weak_five.upgrade().unwrap()));
     /// ```
-    pub fn downgrade(&self) -> Weak<T> {
-        self.inc_weak();
-        Weak { _ptr: self._ptr }
-    }
-}
-
-/// Get the number of weak references to this value.
-#[inline]
-pub fn weak_count<T>(this: &Cc<T>) -> usize { this.weak() - 1 }
-
-/// Get the number of strong references to this value.
-#[inline]
-pub fn strong_count<T>(this: &Cc<T>) -> usize { this.strong() }
-
-/// Returns true if there are no other `Cc` or `Weak<T>` values that share the
-/// same inner value.
-///
-/// # Examples
-///
-/// ```
-/// # #![feature(alloc)]
-/// use bacon_rajan_cc;
-/// use bacon_rajan_cc::Cc;
-///
-/// let five = Cc::new(5);
-///
-/// bacon_rajan_cc::is_unique(&five);
-/// ```
-#[inline]
-pub fn is_unique<T>(rc: &Cc<T>) -> bool {
-    weak_count(rc) == 0 && strong_count(rc) == 1
-}
-
-/// Unwraps the contained value if the `Cc<T>` is unique.
-///
-/// If the `Cc<T>` is not unique, an `Err` is returned with the same `Cc<T>`.
-///
-/// # Examples
-///
-/// ```
-/// # #![feature(alloc)]
-/// use bacon_rajan_cc::{self, Cc};
-///
-/// let x = Cc::new(3);
-/// assert_eq!(bacon_rajan_cc::try_unwrap(x), Ok(3));
-///
-/// let x = Cc::new(4);
-/// let _y = x.clone();
-/// assert_eq!(bacon_rajan_cc::try_unwrap(x), Err(Cc::new(4)));
-/// ```
-#[inline]
-pub fn try_unwrap<T>(rc: Cc<T>) -> Result<T, Cc<T>> {
-    if is_unique(&rc) {
-        unsafe {
-            let val = ptr::read(&*rc); // copy the contained object
-            // destruct the box and skip our Drop
-            // we can ignore the refcounts because we know we're unique
-            deallocate(*rc._ptr as *mut u8, size_of::<CcBox<T>>(),
-                        min_align_of::<CcBox<T>>());
-            forget(rc);
-            Ok(val)
-        }
-    } else {
-        Err(rc)
-    }
-}
-
-/// Returns a mutable reference to the contained value if the `Cc<T>` is unique.
-///
-/// Returns `None` if the `Cc<T>` is not unique.
-///
-/// # Examples
-///
-/// ```
-/// # #![feature(alloc)]
-/// use bacon_rajan_cc::{self, Cc};
-///
-/// let mut x = Cc::new(3);
-/// *bacon_rajan_cc::get_mut(&mut x).unwrap() = 4;
-/// assert_eq!(*x, 4);
-///
-/// let _y = x.clone();
-/// assert!(bacon_rajan_cc::get_mut(&mut x).is_none());
-/// ```
-#[inline]
-pub fn get_mut<T>(rc: &mut Cc<T>) -> Option<&mut T> {
-    if is_unique(rc) {
-        let inner = unsafe { &mut **rc._ptr };
-        Some(&mut inner.value)
-    } else {
-        None
+    #[inline]
+    pub fn new_with_weak(value: T) -> (Cc<T>, Weak<T>) {
+        let cc = Cc::new(value);
+        let weak = cc.downgrade();
+        (cc, weak)
     }
-}
 
-impl<T: Clone> Cc<T> {
-    /// Make a mutable reference from the given `Cc<T>`.
+    /// Constructs a new `Pin<Cc<T>>`.
     ///
-    /// This is also referred to as a copy-on-write operation because the inner
-    /// data is cloned if the reference count is greater than one.
+    /// The value can never be moved out of its box: `Cc<T>` only hands out a
+    /// mutable reference to it through `get_mut` and `make_mut`, which are not
+    /// reachable through a `Pin`.
     ///
     /// # Examples
     ///
     /// ```
-    /// # #![feature(alloc)]
     /// use bacon_rajan_cc::Cc;
     ///
-    /// let mut five = Cc::new(5);
-    ///
-    /// let mut_five = five.make_unique();
+    /// let five = Cc::pin(5);
+    /// assert_eq!(*five, 5);
     /// ```
-    #[inline]
-    pub fn make_unique(&mut self) -> &mut T {
-        if !is_unique(self) {
-            *self = Cc::new((**self).clone())
-        }
-        // This unsafety is ok because we're guaranteed that the pointer
-        // returned is the *only* pointer that will ever be returned to T. Our
-        // reference count is guaranteed to be 1 at this point, and we required
-        // the `Cc<T>` itself to be `mut`, so we're returning the only possible
-        // reference to the inner value.
-        let inner = unsafe { &mut **self._ptr };
-        &mut inner.value
-    }
-}
-
-impl<T> Deref for Cc<T> {
-    type Target = T;
-
-    #[inline(always)]
-    fn deref(&self) -> &T {
-        &self.inner().value
+    pub fn pin(value: T) -> Pin<Cc<T>> {
+        unsafe { Pin::new_unchecked(Cc::new(value)) }
     }
-}
 
-impl<T> Drop for Cc<T> {
-    /// Drops the `Cc<T>`.
+    /// Constructs a new `Cc<T>` whose value is built by `data_fn`, which is
+    /// given a `Weak<T>` to the allocation being constructed.
     ///
-    /// This will decrement the strong reference count. If the strong reference
-    /// count becomes zero and the only other references are `Weak<T>` ones,
-    /// `drop`s the inner value.
+    /// This makes it easy to build values that hold a weak reference to
+    /// themselves. Until `data_fn` returns, the value does not exist yet, so
+    /// calling `upgrade` on the weak reference (or any clone of it) returns
+    /// `None`.
     ///
     /// # Examples
     ///
     /// ```
-    /// # #![feature(alloc)]
-    /// use bacon_rajan_cc::Cc;
-    ///
-    /// {
-    ///     let five = Cc::new(5);
+    /// use bacon_rajan_cc::{Cc, CcTrace, Tracer, Weak};
     ///
-    ///     // stuff
+    /// struct Gadget {
+    ///     me: Weak<Gadget>,
+    /// }
     ///
-    ///     drop(five); // explicit drop
+    /// impl CcTrace for Gadget {
+    ///     fn trace(&self, _tracer: &mut Tracer) { }
     /// }
-    /// {
-    ///     let five = Cc::new(5);
     ///
-    ///     // stuff
+    /// let gadget = Cc::new_cyclic(|me| {
+    ///     assert!(me.upgrade().is_none());
+    ///     Gadget { me: me.clone() }
+    /// });
     ///
-    /// } // implicit drop
+    /// assert!(gadget.me.upgrade().is_some());
     /// ```
-    fn drop(&mut self) {
+    pub fn new_cyclic<F>(data_fn: F) -> Cc<T> where F: FnOnce(&Weak<T>) -> T {
         unsafe {
-            let ptr = *self._ptr;
-            if !ptr.is_null() && ptr as usize != mem::POST_DROP_USIZE {
-                self.dec_strong();
-                if self.strong() == 0 {
-                    ptr::read(&**self); // destroy the contained object
+            // Allocate the box with no strong references yet, and a weak count
+            // of one for `weak`, which becomes the implicit weak reference
+            // owned by the strong ones once the value is in place.
+            let ptr = allocate_box::<T>();
+            if ptr.is_null() {
+                ::alloc::oom();
+            }
+            (*ptr).metadata.strong.set(0);
 
-                    // remove the implicit "strong weak" pointer now that we've
-                    // destroyed the contents.
-                    self.dec_weak();
+            // If `data_fn` panics, dropping `weak` frees the allocation.
+            let weak = Weak { _ptr: NonZero::new(ptr) };
+            let value = data_fn(&weak);
 
-                    if self.weak() == 0 {
-                        deallocate(ptr as *mut u8, size_of::<CcBox<T>>(),
-                                   min_align_of::<CcBox<T>>())
-                    }
-                }
-            }
+            ptr::write(&mut (*ptr).value, value);
+            (*ptr).metadata.strong.set(1);
+            forget(weak);
+            Cc { _ptr: NonZero::new(ptr) }
         }
     }
-}
-
-impl<T> Clone for Cc<T> {
 
-    /// Makes a clone of the `Cc<T>`.
-    ///
-    /// When you clone an `Cc<T>`, it will create another pointer to the data and
-    /// increase the strong reference counter.
+    /// Constructs a new `Cc<T>`, or gives `value` back if the allocation
+    /// fails.
     ///
     /// # Examples
     ///
     /// ```
-    /// # #![feature(alloc)]
     /// use bacon_rajan_cc::Cc;
     ///
-    /// let five = Cc::new(5);
-    ///
-    /// five.clone();
+    /// let five = Cc::try_new(5).ok().unwrap();
+    /// assert_eq!(*five, 5);
     /// ```
-    #[inline]
-    fn clone(&self) -> Cc<T> {
-        self.inc_strong();
-        Cc { _ptr: self._ptr }
+    pub fn try_new(value: T) -> Result<Cc<T>, T> {
+        unsafe {
+            let ptr = allocate_box::<T>();
+            if ptr.is_null() {
+                return Err(value);
+            }
+            ptr::write(&mut (*ptr).value, value);
+            Ok(Cc { _ptr: NonZero::new(ptr) })
+        }
     }
-}
 
-impl<T: Default> Default for Cc<T> {
-    /// Creates a new `Cc<T>`, with the `Default` value for `T`.
+    /// Constructs a new `Cc` with uninitialized contents.
+    ///
+    /// Write the value through [`get_mut_unchecked`](#method.get_mut_unchecked)
+    /// and then call [`assume_init`](#method.assume_init).
     ///
     /// # Examples
     ///
     /// ```
     /// use bacon_rajan_cc::Cc;
     ///
-    /// let x: Cc<i32> = Default::default();
+    /// let mut five = Cc::<u32>::new_uninit();
+    /// unsafe { Cc::get_mut_unchecked(&mut five).as_mut_ptr().write(5) };
+    ///
+    /// let five = unsafe { five.assume_init() };
+    /// assert_eq!(*five, 5);
     /// ```
-    #[inline]
-    fn default() -> Cc<T> {
-        Cc::new(Default::default())
+    pub fn new_uninit() -> Cc<MaybeUninit<T>> {
+        Cc::new(MaybeUninit::uninit())
     }
-}
 
-impl<T: PartialEq> PartialEq for Cc<T> {
-    /// Equality for two `Cc<T>`s.
+    /// Constructs a new `Cc<[T]>` of `len` uninitialized elements.
     ///
-    /// Two `Cc<T>`s are equal if their inner value are equal.
+    /// The counts and the elements share a single allocation. Write the
+    /// elements through [`get_mut`](fn.get_mut.html) while the `Cc` is
+    /// unique, and then call `assume_init`.
     ///
     /// # Examples
     ///
     /// ```
-    /// use bacon_rajan_cc::Cc;
+    /// use std::mem::MaybeUninit;
+    /// use bacon_rajan_cc::{self, Cc};
     ///
-    /// let five = Cc::new(5);
+    /// let mut squares = Cc::<u32>::new_uninit_slice(3);
+    /// for (i, x) in bacon_rajan_cc::get_mut(&mut squares).unwrap().iter_mut().enumerate() {
+    ///     *x = MaybeUninit::new((i * i) as u32);
+    /// }
     ///
-    /// five == Cc::new(5);
+    /// let squares = unsafe { squares.assume_init() };
+    /// assert_eq!(*squares, [0, 1, 4]);
     /// ```
-    #[inline(always)]
-    fn eq(&self, other: &Cc<T>) -> bool { **self == **other }
+    pub fn new_uninit_slice(len: usize) -> Cc<[MaybeUninit<T>]> {
+        unsafe { Cc { _ptr: NonZero::new(allocate_slice_box::<MaybeUninit<T>>(len)) } }
+    }
 
-    /// Inequality for two `Cc<T>`s.
+    /// Consumes the `Cc<T>`, returning a raw pointer to the contained value.
     ///
-    /// Two `Cc<T>`s are unequal if their inner value are unequal.
+    /// The strong reference is not released: to avoid a leak, the pointer
+    /// must eventually be converted back into a `Cc<T>` with
+    /// [`Cc::from_raw`](#method.from_raw).
     ///
     /// # Examples
     ///
@@ -493,19 +493,22 @@ impl<T: PartialEq> PartialEq for Cc<T> {
     /// use bacon_rajan_cc::Cc;
     ///
     /// let five = Cc::new(5);
+    /// let ptr = Cc::into_raw(five);
     ///
-    /// five != Cc::new(5);
+    /// assert_eq!(unsafe { *ptr }, 5);
+    /// # unsafe { Cc::from_raw(ptr) };
     /// ```
-    #[inline(always)]
-    fn ne(&self, other: &Cc<T>) -> bool { **self != **other }
-}
-
-impl<T: Eq> Eq for Cc<T> {}
+    pub fn into_raw(this: Cc<T>) -> *const T {
+        let ptr = Cc::as_ptr(&this);
+        forget(this);
+        ptr
+    }
 
-impl<T: PartialOrd> PartialOrd for Cc<T> {
-    /// Partial comparison for two `Cc<T>`s.
+    /// Constructs a `Cc<T>` from a raw pointer, taking back ownership of the
+    /// strong reference given up by [`Cc::into_raw`](#method.into_raw).
     ///
-    /// The two are compared by calling `partial_cmp()` on their inner values.
+    /// This is unsafe because `ptr` must have been returned by
+    /// `Cc::<T>::into_raw`, and must be converted back at most once.
     ///
     /// # Examples
     ///
@@ -513,17 +516,25 @@ impl<T: PartialOrd> PartialOrd for Cc<T> {
     /// use bacon_rajan_cc::Cc;
     ///
     /// let five = Cc::new(5);
+    /// let ptr = Cc::into_raw(five);
     ///
-    /// five.partial_cmp(&Cc::new(5));
+    /// let five = unsafe { Cc::from_raw(ptr) };
+    /// assert_eq!(*five, 5);
     /// ```
-    #[inline(always)]
-    fn partial_cmp(&self, other: &Cc<T>) -> Option<Ordering> {
-        (**self).partial_cmp(&**other)
+    pub unsafe fn from_raw(ptr: *const T) -> Cc<T> {
+        // The value follows the metadata, padded up to its alignment, just as
+        // in `allocate_slice_box`.
+        let offset = round_up(size_of::<CcBoxMetadata>(), min_align_of::<T>());
+        let box_ptr = (ptr as *const u8).offset(-(offset as isize)) as *mut CcBox<T>;
+        Cc { _ptr: NonZero::new(box_ptr) }
     }
 
-    /// Less-than comparison for two `Cc<T>`s.
+    /// Increments the strong count of the `Cc<T>` the pointer is to, as if it
+    /// had been cloned and the clone given to [`Cc::into_raw`](#method.into_raw).
     ///
-    /// The two are compared by calling `<` on their inner values.
+    /// This is unsafe because `ptr` must have been returned by
+    /// `Cc::<T>::into_raw` or `Cc::<T>::as_ptr`, and the value must still be
+    /// alive.
     ///
     /// # Examples
     ///
@@ -531,504 +542,5231 @@ impl<T: PartialOrd> PartialOrd for Cc<T> {
     /// use bacon_rajan_cc::Cc;
     ///
     /// let five = Cc::new(5);
+    /// let ptr = Cc::as_ptr(&five);
     ///
-    /// five < Cc::new(5);
+    /// unsafe { Cc::increment_strong_count(ptr) };
+    /// assert_eq!(five.strong_count(), 2);
+    /// unsafe { Cc::decrement_strong_count(ptr) };
+    /// assert_eq!(five.strong_count(), 1);
     /// ```
-    #[inline(always)]
-    fn lt(&self, other: &Cc<T>) -> bool { **self < **other }
+    #[inline]
+    pub unsafe fn increment_strong_count(ptr: *const T) {
+        let this = Cc::from_raw(ptr);
+        forget(this.clone());
+        forget(this);
+    }
 
-    /// 'Less-than or equal to' comparison for two `Cc<T>`s.
+    /// Decrements the strong count of the `Cc<T>` the pointer is to, as if the
+    /// `Cc<T>` given up by [`Cc::into_raw`](#method.into_raw) were dropped.
     ///
-    /// The two are compared by calling `<=` on their inner values.
+    /// If that was the last strong reference, the value is dropped, just as
+    /// when dropping a `Cc<T>`.
+    ///
+    /// This is unsafe because `ptr` must have been returned by
+    /// `Cc::<T>::into_raw` or `Cc::<T>::as_ptr`, and the strong reference
+    /// given up must really be owned by the caller.
+    #[inline]
+    pub unsafe fn decrement_strong_count(ptr: *const T) {
+        drop(Cc::from_raw(ptr));
+    }
+}
+
+impl<T: 'static + CcTrace, A: 'static + Allocator> Cc<MaybeUninit<T>, A> {
+    /// Converts to a `Cc<T>`, once the value has been initialized.
+    ///
+    /// The reference counts carry over, and no new allocation is made.
+    ///
+    /// This is unsafe because the value must really be initialized. Until it
+    /// is converted, the value is not traced, so any `Cc`s it holds are kept
+    /// alive rather than collected.
     ///
     /// # Examples
     ///
     /// ```
     /// use bacon_rajan_cc::Cc;
     ///
-    /// let five = Cc::new(5);
+    /// let mut v = Cc::<Vec<u8>>::new_uninit();
+    /// unsafe { Cc::get_mut_unchecked(&mut v).as_mut_ptr().write(vec![1, 2]) };
     ///
-    /// five <= Cc::new(5);
+    /// let v = unsafe { v.assume_init() };
+    /// assert_eq!(*v, [1, 2]);
     /// ```
-    #[inline(always)]
-    fn le(&self, other: &Cc<T>) -> bool { **self <= **other }
+    pub unsafe fn assume_init(self) -> Cc<T, A> {
+        // `MaybeUninit<T>` has the same layout as `T`, so the box does too.
+        let ptr = *self._ptr as *mut CcBox<T, A>;
+        forget(self);
+        Cc { _ptr: NonZero::new(ptr) }
+    }
+}
 
-    /// Greater-than comparison for two `Cc<T>`s.
+impl<T: 'static + CcTrace, A: 'static + Allocator> Cc<[MaybeUninit<T>], A> {
+    /// Converts to a `Cc<[T]>`, once all the elements have been initialized.
     ///
-    /// The two are compared by calling `>` on their inner values.
+    /// As for a single value, the reference counts carry over, no new
+    /// allocation is made, and this is unsafe because every element must
+    /// really be initialized.
+    pub unsafe fn assume_init(self) -> Cc<[T], A> {
+        // `MaybeUninit<T>` has the same layout as `T`, and the length carries
+        // over in the pointer's metadata.
+        let ptr = *self._ptr as *mut CcBox<[T], A>;
+        forget(self);
+        Cc { _ptr: NonZero::new(ptr) }
+    }
+}
+
+impl<T: 'static + CcTrace, A: 'static + Allocator> Cc<T, A> {
+    /// Constructs a new `Cc<T, A>`, allocated with `alloc`.
+    ///
+    /// The allocator is stored with the counts, and the box is freed through
+    /// it once the last `Cc` and `Weak` pointing to it are gone.
     ///
     /// # Examples
     ///
     /// ```
-    /// use bacon_rajan_cc::Cc;
+    /// #![feature(allocator_api)]
     ///
-    /// let five = Cc::new(5);
+    /// use std::alloc::System;
+    /// use bacon_rajan_cc::Cc;
     ///
-    /// five > Cc::new(5);
+    /// let five = Cc::new_in(5, System);
+    /// assert_eq!(*five, 5);
     /// ```
-    #[inline(always)]
-    fn gt(&self, other: &Cc<T>) -> bool { **self > **other }
+    pub fn new_in(value: T, alloc: A) -> Cc<T, A> {
+        let layout = Layout::new::<CcBox<T, A>>();
+        let ptr = match alloc.allocate(layout) {
+            Ok(mem) => mem.as_ptr() as *mut u8 as *mut CcBox<T, A>,
+            Err(_) => handle_alloc_error(layout),
+        };
+        unsafe {
+            ptr::write(ptr, CcBox {
+                metadata: CcBoxMetadata::new(),
+                alloc: alloc,
+                value: value
+            });
+            Cc { _ptr: NonZero::new(ptr) }
+        }
+    }
 
-    /// 'Greater-than or equal to' comparison for two `Cc<T>`s.
+    /// Returns the contained value if this is the only strong reference to
+    /// it, and `None` otherwise.
     ///
-    /// The two are compared by calling `>=` on their inner values.
+    /// Unlike `try_unwrap`, this takes the value even if there are `Weak`
+    /// references: they can't be upgraded afterwards. If this was not the
+    /// only strong reference, it is dropped.
     ///
     /// # Examples
     ///
     /// ```
     /// use bacon_rajan_cc::Cc;
     ///
-    /// let five = Cc::new(5);
+    /// let x = Cc::new(3);
+    /// let weak_x = x.downgrade();
+    /// assert_eq!(Cc::into_inner(x), Some(3));
+    /// assert!(weak_x.upgrade().is_none());
     ///
-    /// five >= Cc::new(5);
+    /// let y = Cc::new(4);
+    /// let _also_y = y.clone();
+    /// assert_eq!(Cc::into_inner(y), None);
     /// ```
-    #[inline(always)]
-    fn ge(&self, other: &Cc<T>) -> bool { **self >= **other }
+    pub fn into_inner(this: Cc<T, A>) -> Option<T> {
+        // the value of a box that the collector is freeing can't be moved out,
+        // as in `try_unwrap`.
+        if this.strong() != 1 || this.color() == Color::White {
+            return None;
+        }
+        unsafe {
+            let value = ptr::read(&*this);
+            // Leave the box as if the last strong reference had been dropped,
+            // without dropping the value. If the box is buffered as a possible
+            // root, the collector frees it once the weak references are gone.
+            this.dec_strong();
+            this.dec_weak();
+            if this.weak() == 0 && !this.buffered() {
+                this.deallocate();
+            }
+            forget(this);
+            Some(value)
+        }
+    }
 }
 
-impl<T: Ord> Ord for Cc<T> {
-    /// Comparison for two `Cc<T>`s.
+impl<T: 'static + CcTrace + ?Sized, A: 'static + Allocator> Cc<T, A> {
+    /// Downgrades the `Cc<T>` to a `Weak<T>` reference.
     ///
-    /// The two are compared by calling `cmp()` on their inner values.
+    /// If `T` has a `downgrade` method of its own, `five.downgrade()` might
+    /// not mean what it seems to, and `Cc::downgrade(&five)` says which one is
+    /// meant.
     ///
     /// # Examples
     ///
     /// ```
+    /// # #![feature(alloc)]
     /// use bacon_rajan_cc::Cc;
     ///
     /// let five = Cc::new(5);
     ///
-    /// five.partial_cmp(&Cc::new(5));
+    /// let weak_five = five.downgrade();
+    /// let also_weak_five = Cc::downgrade(&five);
     /// ```
-    #[inline]
-    fn cmp(&self, other: &Cc<T>) -> Ordering { (**self).cmp(&**other) }
-}
-
-// FIXME (#18248) Make `T` `Sized?`
-impl<T: Hash> Hash for Cc<T> {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        (**self).hash(state);
-    }
-}
-
-impl<T: fmt::Display> fmt::Display for Cc<T> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fmt::Display::fmt(&**self, f)
+    pub fn downgrade(&self) -> Weak<T, A> {
+        self.inc_weak();
+        Weak { _ptr: self._ptr }
     }
-}
 
-impl<T: fmt::Debug> fmt::Debug for Cc<T> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fmt::Debug::fmt(&**self, f)
+    /// Downgrades each of the `Cc<T>`s, and collects the `Weak<T>`s into a
+    /// `Vec`, in the same order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let gadgets = vec![Cc::new(1), Cc::new(2)];
+    /// let observers = Cc::downgrade_all(&gadgets);
+    ///
+    /// assert_eq!(*observers[1].upgrade().unwrap(), 2);
+    /// ```
+    pub fn downgrade_all<'a, I: IntoIterator<Item = &'a Cc<T, A>>>(iter: I) -> Vec<Weak<T, A>> {
+        iter.into_iter().map(Cc::downgrade).collect()
     }
-}
 
-impl<T> fmt::Pointer for Cc<T> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fmt::Pointer::fmt(&*self._ptr, f)
+    /// Returns true if the two `Cc<T>`s point to the same allocation, rather
+    /// than to equal values.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let five = Cc::new(5);
+    /// let same_five = five.clone();
+    /// let other_five = Cc::new(5);
+    ///
+    /// assert!(Cc::ptr_eq(&five, &same_five));
+    /// assert!(!Cc::ptr_eq(&five, &other_five));
+    /// ```
+    #[inline]
+    pub fn ptr_eq(this: &Cc<T, A>, other: &Cc<T, A>) -> bool {
+        // Compare addresses only: two pointers to the same trait object may
+        // carry different vtables.
+        *this._ptr as *const u8 == *other._ptr as *const u8
     }
-}
-
-/// A weak version of `Cc<T>`.
-///
-/// Weak references do not count when determining if the inner value should be
-/// dropped.
-///
-/// See the [module level documentation](./) for more.
-#[unsafe_no_drop_flag]
-pub struct Weak<T> {
-    // FIXME #12808: strange names to try to avoid interfering with
-    // field accesses of the contained type via Deref
-    _ptr: NonZero<*mut CcBox<T>>,
-}
 
-impl<T> Weak<T> {
-
-    /// Upgrades a weak reference to a strong reference.
+    /// Hashes the address of the allocation, so that it agrees with
+    /// `Cc::ptr_eq`.
     ///
-    /// Upgrades the `Weak<T>` reference to an `Cc<T>`, if possible.
-    ///
-    /// Returns `None` if there were no strong references and the data was
-    /// destroyed.
+    /// This is how `CcAddress` hashes, for when mixing identity into a
+    /// custom hash is more convenient than wrapping the `Cc<T>`.
     ///
     /// # Examples
     ///
     /// ```
-    /// # #![feature(alloc)]
+    /// use std::collections::hash_map::DefaultHasher;
+    /// use std::hash::Hasher;
     /// use bacon_rajan_cc::Cc;
     ///
     /// let five = Cc::new(5);
+    /// let hash = |cc: &Cc<i32>| {
+    ///     let mut hasher = DefaultHasher::new();
+    ///     Cc::ptr_hash(cc, &mut hasher);
+    ///     hasher.finish()
+    /// };
     ///
-    /// let weak_five = five.downgrade();
-    ///
-    /// let strong_five: Option<Cc<_>> = weak_five.upgrade();
+    /// assert_eq!(hash(&five), hash(&five.clone()));
     /// ```
-    pub fn upgrade(&self) -> Option<Cc<T>> {
-        if self.strong() == 0 {
-            None
-        } else {
-            self.inc_strong();
-            Some(Cc { _ptr: self._ptr })
-        }
+    #[inline]
+    pub fn ptr_hash<H: Hasher>(this: &Cc<T, A>, state: &mut H) {
+        (*this._ptr as *const u8 as usize).hash(state);
     }
-}
 
-impl<T> Drop for Weak<T> {
-    /// Drops the `Weak<T>`.
+    /// Makes another `Cc<T>` pointing to the same allocation.
     ///
-    /// This will decrement the weak reference count.
+    /// This is the same as `Clone::clone`, but spelled so that it can't be
+    /// mistaken for cloning the inner value when `T` is itself `Clone`.
     ///
     /// # Examples
     ///
     /// ```
-    /// # #![feature(alloc)]
     /// use bacon_rajan_cc::Cc;
     ///
-    /// {
-    ///     let five = Cc::new(5);
-    ///     let weak_five = five.downgrade();
+    /// let v = Cc::new(vec![1, 2, 3]);
+    /// let same_v = Cc::clone_ref(&v);
     ///
-    ///     // stuff
+    /// assert!(Cc::ptr_eq(&v, &same_v));
+    /// ```
+    #[inline]
+    pub fn clone_ref(this: &Cc<T, A>) -> Cc<T, A> {
+        this.clone()
+    }
+
+    /// Makes a handle to a part of the value, such as one of its fields,
+    /// which keeps the whole value alive.
     ///
-    ///     drop(weak_five); // explicit drop
+    /// The projection owns a strong reference to the value, and derefs to
+    /// what `f` returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::{Cc, CcTrace, Tracer};
+    ///
+    /// struct Point { x: i32, y: i32 }
+    ///
+    /// impl CcTrace for Point {
+    ///     fn trace(&self, _tracer: &mut Tracer) {}
     /// }
-    /// {
-    ///     let five = Cc::new(5);
-    ///     let weak_five = five.downgrade();
     ///
-    ///     // stuff
+    /// let point = Cc::new(Point { x: 1, y: 2 });
+    /// let y = Cc::project(&point, |p| &p.y);
+    /// drop(point);
     ///
-    /// } // implicit drop
+    /// assert_eq!(*y, 2);
     /// ```
-    fn drop(&mut self) {
-        unsafe {
-            let ptr = *self._ptr;
-            if !ptr.is_null() && ptr as usize != mem::POST_DROP_USIZE {
-                self.dec_weak();
-                // the weak count starts at 1, and will only go to zero if all
-                // the strong pointers have disappeared.
-                if self.weak() == 0 {
-                    deallocate(ptr as *mut u8, size_of::<CcBox<T>>(),
-                               min_align_of::<CcBox<T>>())
-                }
-            }
-        }
+    pub fn project<U: ?Sized, F: FnOnce(&T) -> &U>(this: &Cc<T, A>, f: F) -> CcProjection<T, U, A> {
+        let ptr = f(&**this) as *const U;
+        CcProjection { owner: this.clone(), ptr: ptr }
     }
-}
 
-impl<T> Clone for Weak<T> {
+    /// Returns a reference to the allocator the box was allocated with.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #![feature(allocator_api)]
+    ///
+    /// use std::alloc::System;
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let five = Cc::new_in(5, System);
+    /// let _: &System = Cc::allocator(&five);
+    /// ```
+    #[inline]
+    pub fn allocator(this: &Cc<T, A>) -> &A {
+        &this.inner().alloc
+    }
 
-    /// Makes a clone of the `Weak<T>`.
+    /// Returns a raw pointer to the contained value.
     ///
-    /// This increases the weak reference count.
+    /// The pointer is the same for every clone of `this`, and stays valid for
+    /// as long as at least one strong reference to the allocation exists.
+    /// Calling `as_ptr` does not affect the reference counts.
     ///
     /// # Examples
     ///
     /// ```
-    /// # #![feature(alloc)]
     /// use bacon_rajan_cc::Cc;
     ///
-    /// let weak_five = Cc::new(5).downgrade();
+    /// let five = Cc::new(5);
+    /// let ptr = Cc::as_ptr(&five);
     ///
-    /// weak_five.clone();
+    /// assert_eq!(unsafe { *ptr }, 5);
+    /// assert_eq!(ptr, Cc::as_ptr(&five.clone()));
     /// ```
     #[inline]
-    fn clone(&self) -> Weak<T> {
-        self.inc_weak();
-        Weak { _ptr: self._ptr }
+    pub fn as_ptr(this: &Cc<T, A>) -> *const T {
+        unsafe { &(**this._ptr).value as *const T }
     }
-}
 
-impl<T: fmt::Debug> fmt::Debug for Weak<T> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "(Weak)")
+    /// Returns a `NonNull` pointer to the contained value.
+    ///
+    /// This is the same pointer as [`as_ptr`](#method.as_ptr) returns, and is
+    /// valid for as long. Calling `as_non_null` does not affect the reference
+    /// counts.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let five = Cc::new(5);
+    /// let ptr = Cc::as_non_null(&five);
+    ///
+    /// assert_eq!(unsafe { *ptr.as_ref() }, 5);
+    /// ```
+    #[inline]
+    pub fn as_non_null(this: &Cc<T, A>) -> NonNull<T> {
+        unsafe { NonNull::new_unchecked(Cc::as_ptr(this) as *mut T) }
     }
-}
-
-#[doc(hidden)]
-trait CcBoxPtr<T> {
-    fn inner(&self) -> &CcBox<T>;
 
+    /// Returns true if the box is part of a garbage cycle that the collector
+    /// is in the middle of freeing.
+    ///
+    /// This can only be seen from the destructors of the values being freed,
+    /// through the `Cc`s they hold to each other, and is meant for
+    /// diagnostics and assertions. Such a `Cc` must not be used for anything
+    /// else: its value might already have been dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let five = Cc::new(5);
+    /// assert!(!Cc::is_being_collected(&five));
+    /// ```
     #[inline]
-    fn strong(&self) -> usize { self.inner().strong.get() }
+    pub fn is_being_collected(this: &Cc<T, A>) -> bool {
+        this.color() == Color::White
+    }
 
+    /// Consumes the `Cc<T>` without releasing its strong reference, and
+    /// returns a reference to the value that lives for the rest of the
+    /// program.
+    ///
+    /// This permanently leaks the allocation: the value is never dropped, and
+    /// the collector never frees it, nor anything it owns, since the leaked
+    /// reference counts as one from outside of any cycle.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let config: &'static str = Cc::leak(Cc::from_str("verbose"));
+    /// assert_eq!(config, "verbose");
+    /// ```
     #[inline]
-    fn inc_strong(&self) { self.inner().strong.set(self.strong() + 1); }
+    pub fn leak(this: Cc<T, A>) -> &'static T {
+        let value = unsafe { &(**this._ptr).value };
+        forget(this);
+        value
+    }
 
+    /// Returns a mutable reference to the contained value, without checking
+    /// that it is safe to do so.
+    ///
+    /// This is unsafe because the caller must make sure that, for as long as
+    /// the returned reference is alive, no other `Cc<T>` or `Weak<T>` pointing
+    /// to the same value is used to read it. `get_mut` is the safe version of
+    /// this, which checks that `this` is unique.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let mut x = Cc::new(String::new());
+    /// unsafe {
+    ///     Cc::get_mut_unchecked(&mut x).push_str("foo");
+    /// }
+    /// assert_eq!(*x, "foo");
+    /// ```
     #[inline]
-    fn dec_strong(&self) { self.inner().strong.set(self.strong() - 1); }
+    pub unsafe fn get_mut_unchecked(this: &mut Cc<T, A>) -> &mut T {
+        &mut (**this._ptr).value
+    }
 
+    /// Get the number of strong references to this value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let five = Cc::new(5);
+    /// let _also_five = five.clone();
+    ///
+    /// assert_eq!(five.strong_count(), 2);
+    /// ```
     #[inline]
-    fn weak(&self) -> usize { self.inner().weak.get() }
+    pub fn strong_count(&self) -> usize { strong_count(self) }
 
+    /// Get the number of weak references to this value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let five = Cc::new(5);
+    /// let _weak_five = five.downgrade();
+    ///
+    /// assert_eq!(five.weak_count(), 1);
+    /// ```
     #[inline]
-    fn inc_weak(&self) { self.inner().weak.set(self.weak() + 1); }
+    pub fn weak_count(&self) -> usize { weak_count(self) }
 
+    /// Keep this box, and everything it can reach, from ever being collected
+    /// as part of a garbage cycle, until `unroot` is called.
+    ///
+    /// The collector treats a rooted box as if something outside of the graph
+    /// pointed to it. This does not keep the value alive once all the `Cc<T>`
+    /// pointing to it are gone.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::{collect_cycles, Cc};
+    ///
+    /// let five = Cc::new(5);
+    /// five.root();
+    /// collect_cycles();
+    /// five.unroot();
+    /// ```
     #[inline]
-    fn dec_weak(&self) { self.inner().weak.set(self.weak() - 1); }
-}
+    pub fn root(&self) {
+        self.metadata().rooted.set(true);
+    }
 
-impl<T> CcBoxPtr<T> for Cc<T> {
-    #[inline(always)]
-    fn inner(&self) -> &CcBox<T> {
-        unsafe {
-            // Safe to assume this here, as if it weren't true, we'd be breaking
-            // the contract anyway.
-            // This allows the null check to be elided in the destructor if we
-            // manipulated the reference count in the same function.
-            assume(!self._ptr.is_null());
-            &(**self._ptr)
+    /// Let this box be collected as part of a garbage cycle again, after a
+    /// call to `root`.
+    ///
+    /// The box might have become garbage while it was rooted, so it is
+    /// buffered for the next collection to look at.
+    #[inline]
+    pub fn unroot(&self) {
+        if self.rooted() {
+            self.metadata().rooted.set(false);
+            collect::possible_root(self);
         }
     }
 }
 
-impl<T> CcBoxPtr<T> for Weak<T> {
-    #[inline(always)]
-    fn inner(&self) -> &CcBox<T> {
+impl<T: 'static + CcTrace + Clone> Cc<[T]> {
+    /// Constructs a new `Cc<[T]>` holding clones of the elements of `slice`.
+    ///
+    /// The counts and the elements share a single allocation.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let numbers: Cc<[i32]> = Cc::from_slice(&[1, 2, 3]);
+    /// assert_eq!(numbers[1], 2);
+    /// ```
+    pub fn from_slice(slice: &[T]) -> Cc<[T]> {
         unsafe {
-            // Safe to assume this here, as if it weren't true, we'd be breaking
-            // the contract anyway.
-            // This allows the null check to be elided in the destructor if we
-            // manipulated the reference count in the same function.
-            assume(!self._ptr.is_null());
-            &(**self._ptr)
+            let ptr = allocate_slice_box::<T>(slice.len());
+            let elems = &mut (*ptr).value as *mut [T] as *mut T;
+            let mut guard = PartialSliceBox { ptr: ptr, written: 0 };
+            for (i, x) in slice.iter().enumerate() {
+                ptr::write(elems.offset(i as isize), x.clone());
+                guard.written = i + 1;
+            }
+            forget(guard);
+            Cc { _ptr: NonZero::new(ptr) }
         }
     }
 }
 
-pub type Tracer = FnMut(&CcTrace);
+impl<T: 'static + CcTrace, A: 'static + Allocator> Cc<[T], A> {
+    /// Returns a reference to the element at index `i`, or `None` if `i` is
+    /// out of bounds.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let numbers: Cc<[i32]> = Cc::from_slice(&[1, 2, 3]);
+    /// assert_eq!(numbers.get(1), Some(&2));
+    /// assert_eq!(numbers.get(3), None);
+    /// ```
+    #[inline]
+    pub fn get(&self, i: usize) -> Option<&T> {
+        (**self).get(i)
+    }
 
-pub trait CcTrace: fmt::Debug {
-    fn trace(&self, tracer: &mut Tracer);
-}
+    /// Returns the number of elements in the slice.
+    #[inline]
+    pub fn len(&self) -> usize {
+        (**self).len()
+    }
 
-#[cfg(test)]
-mod tests {
-    #![plugin(bacon_rajan_cc)]
+    /// Returns `true` if the slice has no elements.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        (**self).is_empty()
+    }
+}
 
-    use super::{Cc, CcTrace, Weak, weak_count, strong_count};
-    use std::boxed::Box;
-    use std::cell::RefCell;
-    use std::option::Option;
-    use std::option::Option::{Some, None};
-    use std::result::Result::{Err, Ok};
-    use std::mem::drop;
-    use std::clone::Clone;
+impl<T: 'static + CcTrace> FromIterator<T> for Cc<[T]> {
+    /// Collects the elements of `iter` into a `Cc<[T]>`.
+    ///
+    /// The elements are first collected into a `Vec<T>`, and then moved into
+    /// a single allocation with the counts.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let evens: Cc<[u32]> = (0..5).map(|x| x * 2).collect();
+    ///
+    /// assert_eq!(&*evens, &[0, 2, 4, 6, 8]);
+    /// ```
+    fn from_iter<I: IntoIterator<Item=T>>(iter: I) -> Cc<[T]> {
+        let elems: Vec<T> = iter.into_iter().collect();
+        Cc::from(elems)
+    }
+}
 
-    // trace_macros!(true);
+impl<T: 'static + CcTrace> From<Vec<T>> for Cc<[T]> {
+    /// Moves the elements of `v` into a new `Cc<[T]>`, and frees the vector's
+    /// buffer.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let xs: Cc<[i32]> = Cc::from(vec![1, 2, 3]);
+    /// assert_eq!(xs[2], 3);
+    /// ```
+    fn from(mut v: Vec<T>) -> Cc<[T]> {
+        unsafe {
+            let ptr = allocate_slice_box::<T>(v.len());
+            ptr::copy_nonoverlapping(v.as_ptr(),
+                                     &mut (*ptr).value as *mut [T] as *mut T,
+                                     v.len());
+            // The elements were moved out, so only free the vector's buffer.
+            v.set_len(0);
+            Cc { _ptr: NonZero::new(ptr) }
+        }
+    }
+}
 
-    // #[derive(CcTrace, Debug)]
+impl<T: 'static + CcTrace> From<Box<[T]>> for Cc<[T]> {
+    /// Moves the elements of `b` into a new `Cc<[T]>`, and frees the box.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let b: Box<[i32]> = vec![1, 2, 3].into_boxed_slice();
+    /// let xs: Cc<[i32]> = Cc::from(b);
+    /// assert_eq!(xs[0], 1);
+    /// ```
+    #[inline]
+    fn from(b: Box<[T]>) -> Cc<[T]> {
+        Cc::from(b.into_vec())
+    }
+}
+
+impl<'a, T: 'static + CcTrace> IntoIterator for &'a Cc<[T]> {
+    type Item = &'a T;
+    type IntoIter = slice::Iter<'a, T>;
+
+    /// Iterates over references to the elements of the slice.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let xs = Cc::from_slice(&[1, 2, 3]);
+    ///
+    /// let mut sum = 0;
+    /// for x in &xs {
+    ///     sum += *x;
+    /// }
+    /// assert_eq!(sum, 6);
+    /// ```
+    #[inline]
+    fn into_iter(self) -> slice::Iter<'a, T> {
+        (**self).iter()
+    }
+}
+
+impl<A: 'static + Allocator> Cc<Any, A> {
+    /// Converts a `Cc<Any>` into a `Cc<T>` pointing to the same box, if the
+    /// value is a `T`. Otherwise, the `Cc<Any>` is given back.
+    ///
+    /// Values behind a `Cc<Any>` are traced with the function registered for
+    /// their type by `register_trace_fn`, rather than with their own
+    /// `CcTrace` impl.
+    ///
+    /// There is no `TryFrom<Cc<Any>>` impl to go with this: for a `T` of
+    /// `Cc<Any>`, it would overlap with the one the standard library derives
+    /// from `From<T> for Cc<T>`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::any::Any;
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let five: Cc<Any> = Cc::new(5u32);
+    /// let five: Cc<u32> = five.downcast().ok().unwrap();
+    /// assert_eq!(*five, 5);
+    /// ```
+    pub fn downcast<T: Any + CcTrace>(self) -> Result<Cc<T, A>, Cc<Any, A>> {
+        if (*self).is::<T>() {
+            // The box was made as a `CcBox<T, A>`, and only the vtable has to
+            // be dropped from the pointer.
+            let ptr = *self._ptr as *mut CcBox<T, A>;
+            forget(self);
+            Ok(Cc { _ptr: unsafe { NonZero::new(ptr) } })
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl<T: 'static + CcTrace, const N: usize> TryFrom<Cc<[T]>> for Cc<[T; N]> {
+    type Error = Cc<[T]>;
+
+    /// Converts a `Cc<[T]>` into a `Cc<[T; N]>` pointing to the same box, if
+    /// the slice has exactly `N` elements. Otherwise, the `Cc<[T]>` is given
+    /// back.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::convert::TryFrom;
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let xs: Cc<[u32]> = Cc::from_slice(&[1, 2, 3]);
+    /// let xs: Cc<[u32; 3]> = Cc::try_from(xs).ok().unwrap();
+    ///
+    /// assert_eq!(*xs, [1, 2, 3]);
+    /// ```
+    fn try_from(slice: Cc<[T]>) -> Result<Cc<[T; N]>, Cc<[T]>> {
+        if slice.len() == N {
+            // A `CcBox<[T]>` of `N` elements has the same layout as a
+            // `CcBox<[T; N]>`, so only the length has to be dropped from the
+            // pointer.
+            let ptr = *slice._ptr as *mut CcBox<[T; N]>;
+            forget(slice);
+            Ok(Cc { _ptr: unsafe { NonZero::new(ptr) } })
+        } else {
+            Err(slice)
+        }
+    }
+}
+
+impl Cc<str> {
+    /// Constructs a new `Cc<str>` holding a copy of `s`.
+    ///
+    /// The counts and the string's bytes share a single allocation.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let hello = Cc::from_str("hello");
+    /// assert_eq!(&*hello, "hello");
+    /// ```
+    pub fn from_str(s: &str) -> Cc<str> {
+        unsafe {
+            let ptr = allocate_slice_box::<u8>(s.len());
+            let bytes = &mut (*ptr).value as *mut [u8] as *mut u8;
+            ptr::copy_nonoverlapping(s.as_ptr(), bytes, s.len());
+            Cc { _ptr: NonZero::new(ptr as *mut CcBox<str>) }
+        }
+    }
+}
+
+impl<'a> PartialEq<&'a str> for Cc<str> {
+    #[inline(always)]
+    fn eq(&self, other: &&'a str) -> bool { &**self == *other }
+}
+
+// Allocate a `CcBox<T>` and initialize its metadata, or return null if the
+// allocation fails. The value is left uninitialized.
+unsafe fn allocate_box<T: 'static + CcTrace>() -> *mut CcBox<T> {
+    if allocation_fails() {
+        return ptr::null_mut();
+    }
+
+    // Every box is freed through its allocator, so a `Global` one has to be
+    // allocated through it too.
+    let ptr = match Global.allocate(Layout::new::<CcBox<T>>()) {
+        Ok(mem) => mem.as_ptr() as *mut u8 as *mut CcBox<T>,
+        Err(_) => return ptr::null_mut(),
+    };
+    ptr::write(&mut (*ptr).metadata, CcBoxMetadata::new());
+    ptr::write(&mut (*ptr).alloc, Global);
+    ptr
+}
+
+#[cfg(not(test))]
+#[inline(always)]
+fn allocation_fails() -> bool { false }
+
+// Tests can make allocations fail on purpose, to exercise the paths that
+// handle it.
+#[cfg(test)]
+cc_thread_local!(static FAIL_ALLOCATIONS: Cell<bool> = Cell::new(false));
+
+#[cfg(test)]
+fn allocation_fails() -> bool { FAIL_ALLOCATIONS.with(|f| f.get()) }
+
+// Allocate a `CcBox<[T]>` with room for `len` elements, and initialize its
+// metadata. The elements are left uninitialized.
+unsafe fn allocate_slice_box<T: 'static + CcTrace>(len: usize) -> *mut CcBox<[T]> {
+    // The elements come right after the metadata, padded up to their
+    // alignment, and the whole box is padded up to the larger of the two
+    // alignments. This is the same layout the compiler gives a `CcBox<[T]>`,
+    // which is what `Layout::for_value` uses to free it.
+    let elem_align = min_align_of::<T>();
+    let align = cmp::max(min_align_of::<CcBoxMetadata>(), elem_align);
+    let offset = round_up(size_of::<CcBoxMetadata>(), elem_align);
+    // `new_uninit_slice` takes any `len`, so a size that doesn't fit must not
+    // wrap around into a box too small for the elements.
+    let size = len.checked_mul(size_of::<T>())
+        .and_then(|n| n.checked_add(offset))
+        .and_then(|n| n.checked_add(align - 1))
+        .and_then(|n| if n <= isize::max_value() as usize { Some(n) } else { None })
+        .expect("capacity overflow") & !(align - 1);
+
+    let layout = Layout::from_size_align_unchecked(size, align);
+    let mem = match Global.allocate(layout) {
+        Ok(mem) => mem.as_ptr() as *mut u8,
+        Err(_) => handle_alloc_error(layout),
+    };
+
+    let ptr = slice::from_raw_parts_mut(mem as *mut T, len) as *mut [T] as *mut CcBox<[T]>;
+    ptr::write(&mut (*ptr).metadata, CcBoxMetadata::new());
+    ptr::write(&mut (*ptr).alloc, Global);
+    ptr
+}
+
+// A slice box that is being filled in. If filling it panics, the elements
+// written so far are dropped, and the box is freed.
+struct PartialSliceBox<T: 'static + CcTrace> {
+    ptr: *mut CcBox<[T]>,
+    written: usize,
+}
+
+impl<T: 'static + CcTrace> Drop for PartialSliceBox<T> {
+    fn drop(&mut self) {
+        unsafe {
+            let elems = &mut (*self.ptr).value as *mut [T] as *mut T;
+            drop_in_place(slice::from_raw_parts_mut(elems, self.written) as *mut [T]);
+            (*self.ptr).deallocate();
+        }
+    }
+}
+
+// Whether incrementing a reference count of `count` would overflow. A count
+// that wrapped around would let the box be freed while it is still in use,
+// which only takes a loop of `mem::forget(cc.clone())` to provoke.
+#[inline]
+fn increment_overflows(count: usize) -> bool {
+    count == usize::max_value()
+}
+
+// Like `Rc`, abort rather than panic on overflow: unwinding could run code
+// that uses the box whose count is wrong.
+#[cold]
+#[inline(never)]
+fn count_overflow() -> ! {
+    #[cfg(feature = "std")]
+    {
+        use std::io::Write;
+        let _ = io::stderr().write_all(b"bacon_rajan_cc: reference count overflow, aborting\n");
+    }
+    unsafe { abort() }
+}
+
+#[inline]
+fn round_up(n: usize, align: usize) -> usize {
+    (n + align - 1) & !(align - 1)
+}
+
+/// Get the number of weak references to this value.
+#[inline]
+pub fn weak_count<T: 'static + CcTrace + ?Sized, A: 'static + Allocator>(this: &Cc<T, A>) -> usize { this.weak() - 1 }
+
+/// Get the number of strong references to this value.
+#[inline]
+pub fn strong_count<T: 'static + CcTrace + ?Sized, A: 'static + Allocator>(this: &Cc<T, A>) -> usize { this.strong() }
+
+/// Get the number of strong and weak references to this value, as
+/// `(strong_count, weak_count)`.
+///
+/// # Examples
+///
+/// ```
+/// use bacon_rajan_cc;
+/// use bacon_rajan_cc::Cc;
+///
+/// let five = Cc::new(5);
+/// let _also_five = five.clone();
+/// let _weak_five = five.downgrade();
+///
+/// assert_eq!(bacon_rajan_cc::ref_counts(&five), (2, 1));
+/// ```
+#[inline]
+pub fn ref_counts<T: 'static + CcTrace + ?Sized, A: 'static + Allocator>(this: &Cc<T, A>) -> (usize, usize) {
+    (strong_count(this), weak_count(this))
+}
+
+/// Formats the reference counts and collector state of a `Cc<T>`'s box, for
+/// debugging the collector.
+///
+/// The weak count is the raw one, which includes the weak reference that all
+/// the strong ones share while there are any, so it is one more than
+/// `weak_count` for a live value.
+///
+/// # Examples
+///
+/// ```
+/// use bacon_rajan_cc;
+/// use bacon_rajan_cc::Cc;
+///
+/// let five = Cc::new(5);
+///
+/// let metadata = bacon_rajan_cc::debug_metadata(&five);
+/// assert!(metadata.contains("strong: 1"));
+/// assert!(metadata.contains("color: Black"));
+/// ```
+pub fn debug_metadata<T: 'static + CcTrace + ?Sized, A: 'static + Allocator>(this: &Cc<T, A>) -> String {
+    let mut s = String::new();
+    let _ = fmt::write(&mut s, format_args!("{:?}", this.metadata()));
+    s
+}
+
+/// Returns true if there are no other `Cc` or `Weak<T>` values that share the
+/// same inner value.
+///
+/// # Examples
+///
+/// ```
+/// # #![feature(alloc)]
+/// use bacon_rajan_cc;
+/// use bacon_rajan_cc::Cc;
+///
+/// let five = Cc::new(5);
+///
+/// bacon_rajan_cc::is_unique(&five);
+/// ```
+#[inline]
+pub fn is_unique<T: 'static + CcTrace + ?Sized, A: 'static + Allocator>(rc: &Cc<T, A>) -> bool {
+    weak_count(rc) == 0 && strong_count(rc) == 1
+}
+
+/// Returns true if there are no other `Cc` values that share the same inner
+/// value, no matter how many `Weak<T>` values do.
+///
+/// Unlike `is_unique`, this does not mean that `rc` is the only way to get at
+/// the value: any of the `Weak<T>` values can still be upgraded to a new `Cc`
+/// and read the value. It is therefore not enough on its own to decide that
+/// handing out a mutable reference to the value is safe.
+///
+/// # Examples
+///
+/// ```
+/// # #![feature(alloc)]
+/// use bacon_rajan_cc::{self, Cc};
+///
+/// let five = Cc::new(5);
+/// let _weak_five = five.downgrade();
+///
+/// assert!(bacon_rajan_cc::has_sole_strong(&five));
+/// assert!(!bacon_rajan_cc::is_unique(&five));
+/// ```
+#[inline]
+pub fn has_sole_strong<T: 'static + CcTrace + ?Sized, A: 'static + Allocator>(rc: &Cc<T, A>) -> bool {
+    strong_count(rc) == 1
+}
+
+/// Unwraps the contained value if the `Cc<T>` is unique.
+///
+/// If the `Cc<T>` is not unique, an `Err` is returned with the same `Cc<T>`.
+///
+/// # Examples
+///
+/// ```
+/// # #![feature(alloc)]
+/// use bacon_rajan_cc::{self, Cc};
+///
+/// let x = Cc::new(3);
+/// assert_eq!(bacon_rajan_cc::try_unwrap(x), Ok(3));
+///
+/// let x = Cc::new(4);
+/// let _y = x.clone();
+/// assert_eq!(bacon_rajan_cc::try_unwrap(x), Err(Cc::new(4)));
+/// ```
+#[inline]
+pub fn try_unwrap<T: 'static + CcTrace, A: 'static + Allocator>(rc: Cc<T, A>) -> Result<T, Cc<T, A>> {
+    // a box that the collector is freeing looks unique from the `Drop` of
+    // another box in the same garbage cycle, but the collector is going to
+    // drop its value and free it, so it can't be taken apart here.
+    if is_unique(&rc) && rc.color() != Color::White {
+        unsafe {
+            let val = ptr::read(&*rc); // copy the contained object
+            // destruct the box and skip our Drop
+            // we can ignore the refcounts because we know we're unique, but if
+            // the box is in the roots buffer, the collector will free it later.
+            rc.dec_strong();
+            rc.dec_weak();
+            if !rc.buffered() {
+                rc.deallocate();
+            }
+            forget(rc);
+            Ok(val)
+        }
+    } else {
+        Err(rc)
+    }
+}
+
+/// Returns a mutable reference to the contained value if the `Cc<T>` is unique.
+///
+/// Returns `None` if the `Cc<T>` is not unique.
+///
+/// A `Weak<T>` makes the `Cc<T>` not unique even if it hasn't been upgraded:
+/// it could be upgraded while the mutable reference is still alive, which
+/// would alias it. To mutate in place when there is a single strong reference
+/// and any number of `Weak<T>`s, use `Cc::make_mut`, which moves the value out
+/// of reach of the `Weak<T>`s instead of cloning it.
+///
+/// # Examples
+///
+/// ```
+/// # #![feature(alloc)]
+/// use bacon_rajan_cc::{self, Cc};
+///
+/// let mut x = Cc::new(3);
+/// *bacon_rajan_cc::get_mut(&mut x).unwrap() = 4;
+/// assert_eq!(*x, 4);
+///
+/// let _y = x.clone();
+/// assert!(bacon_rajan_cc::get_mut(&mut x).is_none());
+/// ```
+#[inline]
+pub fn get_mut<T: 'static + CcTrace + ?Sized, A: 'static + Allocator>(rc: &mut Cc<T, A>) -> Option<&mut T> {
+    if is_unique(rc) {
+        let inner = unsafe { &mut **rc._ptr };
+        Some(&mut inner.value)
+    } else {
+        None
+    }
+}
+
+impl<T: 'static + CcTrace + Clone> Cc<T> {
+    /// Make a mutable reference from the given `Cc<T>`.
+    ///
+    /// This is also referred to as a copy-on-write operation because the inner
+    /// data is cloned if the strong reference count is greater than one.
+    ///
+    /// If this is the only strong reference but there are `Weak` references,
+    /// the value is moved into a new allocation instead of being cloned, and
+    /// the `Weak` references are left dangling: they will never upgrade again.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![feature(alloc)]
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let mut five = Cc::new(5);
+    ///
+    /// let mut_five = five.make_mut();
+    /// ```
+    #[inline]
+    pub fn make_mut(&mut self) -> &mut T {
+        // the value of a box that the collector is freeing can't be moved out,
+        // as in `try_unwrap`.
+        if self.strong() != 1 || self.color() == Color::White {
+            *self = Cc::new((**self).clone())
+        } else if self.weak() != 1 {
+            unsafe {
+                // Move the value out, and give the weak references the
+                // allocation, as if the last strong reference had been
+                // dropped. If the box is buffered as a possible root, the
+                // collector frees it once the weak references are gone.
+                let value = ptr::read(&**self);
+                self.dec_strong();
+                self.dec_weak();
+                forget(mem::replace(self, Cc::new(value)));
+            }
+        }
+        // This unsafety is ok because we're guaranteed that the pointer
+        // returned is the *only* pointer that will ever be returned to T. Our
+        // reference count is guaranteed to be 1 at this point, and we required
+        // the `Cc<T>` itself to be `mut`, so we're returning the only possible
+        // reference to the inner value.
+        let inner = unsafe { &mut **self._ptr };
+        &mut inner.value
+    }
+
+    /// Apply `f` to the value, cloning it first if it is shared.
+    ///
+    /// This is the same as `f(self.make_mut())`, and leaves any other `Cc<T>`
+    /// pointing to the old value unchanged.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let mut counter = Cc::new(5);
+    /// let snapshot = counter.clone();
+    ///
+    /// counter.update(|n| *n += 1);
+    /// assert_eq!(*counter, 6);
+    /// assert_eq!(*snapshot, 5);
+    /// ```
+    #[inline]
+    pub fn update<F: FnOnce(&mut T)>(&mut self, f: F) {
+        f(self.make_mut())
+    }
+
+    /// Make a mutable reference from the given `Cc<T>`.
+    ///
+    /// This is the old name of [`make_mut`](#method.make_mut).
+    #[inline]
+    #[deprecated(note = "renamed to `make_mut`")]
+    pub fn make_unique(&mut self) -> &mut T {
+        self.make_mut()
+    }
+
+    /// Returns the contained value, moving it out if the `Cc<T>` is unique,
+    /// and cloning it otherwise.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let x = Cc::new(String::from("hello"));
+    /// let y = x.clone();
+    ///
+    /// assert_eq!(Cc::unwrap_or_clone(x), "hello");
+    /// assert_eq!(Cc::unwrap_or_clone(y), "hello");
+    /// ```
+    #[inline]
+    pub fn unwrap_or_clone(this: Cc<T>) -> T {
+        try_unwrap(this).unwrap_or_else(|rc| (*rc).clone())
+    }
+}
+
+impl<T, U, A> CoerceUnsized<Cc<U, A>> for Cc<T, A>
+    where T: 'static + CcTrace + ?Sized + Unsize<U>, U: 'static + CcTrace + ?Sized,
+          A: 'static + Allocator {}
+
+// Moving a `Cc<T>` never moves the value, which is only ever reachable through
+// a shared reference.
+impl<T: 'static + CcTrace + ?Sized, A: 'static + Allocator> Unpin for Cc<T, A> {}
+
+impl<T: 'static + CcTrace + ?Sized, A: 'static + Allocator> Deref for Cc<T, A> {
+    type Target = T;
+
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        &self.inner().value
+    }
+}
+
+impl<T: 'static + CcTrace + ?Sized, A: 'static + Allocator> Drop for Cc<T, A> {
+    /// Drops the `Cc<T>`.
+    ///
+    /// This will decrement the strong reference count. If the strong reference
+    /// count becomes zero and the only other references are `Weak<T>` ones,
+    /// `drop`s the inner value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![feature(alloc)]
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// {
+    ///     let five = Cc::new(5);
+    ///
+    ///     // stuff
+    ///
+    ///     drop(five); // explicit drop
+    /// }
+    /// {
+    ///     let five = Cc::new(5);
+    ///
+    ///     // stuff
+    ///
+    /// } // implicit drop
+    /// ```
+    fn drop(&mut self) {
+        // A `Cc` is dropped exactly once, where it was last owned, and never
+        // after it was moved out of, so there is no need to check for a
+        // pointer that was already dropped.
+        self.dec_strong();
+        if self.color() == Color::White {
+            // This box is part of a garbage cycle that the collector is in the
+            // middle of freeing, and it will take care of the value and the
+            // allocation itself.
+            return;
+        }
+
+        if self.strong() == 0 {
+            unsafe { drop_dead_box(self); }
+        } else {
+            collect::possible_root(self);
+        }
+    }
+}
+
+// The boxes whose last strong reference went away while the value of another
+// box was being dropped, and whose values are still to be dropped. This is
+// `None` when no value is being dropped.
+//
+// Dropping a value drops the `Cc`s it owns, which might drop their values in
+// turn, and so on. Doing that recursively would overflow the stack on long
+// chains of boxes, so instead the outermost drop keeps dropping values off of
+// this list until it is empty.
+cc_thread_local!(static PENDING_DROPS: RefCell<Option<Vec<Box<CcBoxPtr>>>> = RefCell::new(None));
+
+// Drops the values of the boxes in `PENDING_DROPS` until there are none left,
+// and then resets it. This happens even if dropping a value panicked, like a
+// `Vec` still drops the rest of its elements, and a second panic aborts.
+struct PendingDropsGuard;
+
+impl Drop for PendingDropsGuard {
+    fn drop(&mut self) {
+        loop {
+            let next = PENDING_DROPS.with(|p| p.borrow_mut().as_mut().unwrap().pop());
+            match next {
+                Some(b) => unsafe { finish_dropping(&*b) },
+                None => break,
+            }
+        }
+        PENDING_DROPS.with(|p| *p.borrow_mut() = None);
+    }
+}
+
+// Drop the value of a box whose strong count just went to zero, and free the
+// box if nothing else is keeping it around.
+unsafe fn drop_dead_box(b: &CcBoxPtr) {
+    // Until its value is gone, the box is white, like one the collector is
+    // freeing: it can't be upgraded, and it still has its implicit weak
+    // reference, which `Weak::weak_count` leaves out.
+    b.metadata().color.set(Color::White);
+    let nested = PENDING_DROPS.with(|p| {
+        let mut pending = p.borrow_mut();
+        let nested = match *pending {
+            Some(ref mut boxes) => {
+                boxes.push(b.box_ref());
+                true
+            }
+            None => false,
+        };
+        if !nested {
+            *pending = Some(Vec::new());
+        }
+        nested
+    });
+    if nested {
+        return;
+    }
+
+    let _guard = PendingDropsGuard;
+    finish_dropping(b);
+}
+
+unsafe fn finish_dropping(b: &CcBoxPtr) {
+    // Release the box even if the value's destructor panics. The rest of the
+    // value is still dropped while unwinding, and nothing can get at it
+    // anymore, so the box is as dead as if the destructor had returned.
+    let _release = ReleaseDeadBox(b);
+    b.drop_value(); // destroy the contained object
+}
+
+// Gives up the implicit weak reference of a box whose value was dropped, and
+// frees the box if nothing else is keeping it around.
+struct ReleaseDeadBox<'a>(&'a CcBoxPtr);
+
+impl<'a> Drop for ReleaseDeadBox<'a> {
+    fn drop(&mut self) {
+        let b = self.0;
+        // remove the implicit "strong weak" pointer now that we've destroyed
+        // the contents.
+        b.metadata().color.set(Color::Black);
+        b.dec_weak();
+
+        // if the box is still in the roots buffer, the collector frees it when
+        // it gets to it.
+        if b.weak() == 0 && !b.buffered() {
+            unsafe { b.deallocate(); }
+        }
+    }
+}
+
+impl<T: 'static + CcTrace + ?Sized, A: 'static + Allocator> Clone for Cc<T, A> {
+
+    /// Makes a clone of the `Cc<T>`.
+    ///
+    /// When you clone an `Cc<T>`, it will create another pointer to the data and
+    /// increase the strong reference counter.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![feature(alloc)]
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let five = Cc::new(5);
+    ///
+    /// five.clone();
+    /// ```
+    #[inline]
+    fn clone(&self) -> Cc<T, A> {
+        self.inc_strong();
+        Cc { _ptr: self._ptr }
+    }
+
+    /// Makes `self` point to the same data as `source`.
+    ///
+    /// If they already point to the same data, the counts are left alone.
+    #[inline]
+    fn clone_from(&mut self, source: &Cc<T, A>) {
+        if !Cc::ptr_eq(self, source) {
+            *self = source.clone();
+        }
+    }
+}
+
+impl<T: 'static + CcTrace + Default> Default for Cc<T> {
+    /// Creates a new `Cc<T>`, with the `Default` value for `T`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let x: Cc<i32> = Default::default();
+    /// ```
+    #[inline]
+    fn default() -> Cc<T> {
+        Cc::new(Default::default())
+    }
+}
+
+impl<T: 'static + CcTrace> From<T> for Cc<T> {
+    /// Moves `value` into a new `Cc<T>`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let x: Cc<i32> = Cc::from(5);
+    /// let y: Cc<i32> = 5.into();
+    /// ```
+    #[inline]
+    fn from(value: T) -> Cc<T> {
+        Cc::new(value)
+    }
+}
+
+impl<T: 'static + CcTrace + PartialEq + ?Sized, A: 'static + Allocator> Cc<T, A> {
+    /// Returns true if the two `Cc<T>`s point to the same allocation, or
+    /// otherwise if their inner values are equal.
+    ///
+    /// This skips comparing the values when they are shared, which is the
+    /// common case for interned values. Unlike `==`, it treats a value that
+    /// isn't equal to itself, like a NaN, as equal when it is shared.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let five = Cc::new(5);
+    ///
+    /// assert!(Cc::ptr_or_value_eq(&five, &five.clone()));
+    /// assert!(Cc::ptr_or_value_eq(&five, &Cc::new(5)));
+    /// assert!(!Cc::ptr_or_value_eq(&five, &Cc::new(6)));
+    /// ```
+    #[inline]
+    pub fn ptr_or_value_eq(this: &Cc<T, A>, other: &Cc<T, A>) -> bool {
+        Cc::ptr_eq(this, other) || **this == **other
+    }
+}
+
+impl<T: 'static + CcTrace + PartialEq + ?Sized, A: 'static + Allocator> PartialEq for Cc<T, A> {
+    /// Equality for two `Cc<T>`s.
+    ///
+    /// Two `Cc<T>`s are equal if their inner value are equal.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let five = Cc::new(5);
+    ///
+    /// five == Cc::new(5);
+    /// ```
+    #[inline(always)]
+    fn eq(&self, other: &Cc<T, A>) -> bool { **self == **other }
+
+    /// Inequality for two `Cc<T>`s.
+    ///
+    /// Two `Cc<T>`s are unequal if their inner value are unequal.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let five = Cc::new(5);
+    ///
+    /// five != Cc::new(5);
+    /// ```
+    #[inline(always)]
+    fn ne(&self, other: &Cc<T, A>) -> bool { **self != **other }
+}
+
+impl<T: 'static + CcTrace + Eq + ?Sized, A: 'static + Allocator> Eq for Cc<T, A> {}
+
+impl<T: 'static + CcTrace + PartialOrd + ?Sized, A: 'static + Allocator> PartialOrd for Cc<T, A> {
+    /// Partial comparison for two `Cc<T>`s.
+    ///
+    /// The two are compared by calling `partial_cmp()` on their inner values.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let five = Cc::new(5);
+    ///
+    /// five.partial_cmp(&Cc::new(5));
+    /// ```
+    #[inline(always)]
+    fn partial_cmp(&self, other: &Cc<T, A>) -> Option<Ordering> {
+        (**self).partial_cmp(&**other)
+    }
+
+    /// Less-than comparison for two `Cc<T>`s.
+    ///
+    /// The two are compared by calling `<` on their inner values.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let five = Cc::new(5);
+    ///
+    /// five < Cc::new(5);
+    /// ```
+    #[inline(always)]
+    fn lt(&self, other: &Cc<T, A>) -> bool { **self < **other }
+
+    /// 'Less-than or equal to' comparison for two `Cc<T>`s.
+    ///
+    /// The two are compared by calling `<=` on their inner values.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let five = Cc::new(5);
+    ///
+    /// five <= Cc::new(5);
+    /// ```
+    #[inline(always)]
+    fn le(&self, other: &Cc<T, A>) -> bool { **self <= **other }
+
+    /// Greater-than comparison for two `Cc<T>`s.
+    ///
+    /// The two are compared by calling `>` on their inner values.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let five = Cc::new(5);
+    ///
+    /// five > Cc::new(5);
+    /// ```
+    #[inline(always)]
+    fn gt(&self, other: &Cc<T, A>) -> bool { **self > **other }
+
+    /// 'Greater-than or equal to' comparison for two `Cc<T>`s.
+    ///
+    /// The two are compared by calling `>=` on their inner values.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let five = Cc::new(5);
+    ///
+    /// five >= Cc::new(5);
+    /// ```
+    #[inline(always)]
+    fn ge(&self, other: &Cc<T, A>) -> bool { **self >= **other }
+}
+
+impl<T: 'static + CcTrace + PartialEq + ?Sized, A: 'static + Allocator> PartialEq<T> for Cc<T, A> {
+    /// Equality for a `Cc<T>` and a `T`.
+    ///
+    /// The two are equal if the inner value is equal to the other value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let five = Cc::new(5);
+    ///
+    /// assert!(five == 5);
+    /// ```
+    #[inline(always)]
+    fn eq(&self, other: &T) -> bool { **self == *other }
+}
+
+impl<T: 'static + CcTrace + PartialOrd + ?Sized, A: 'static + Allocator> PartialOrd<T> for Cc<T, A> {
+    /// Partial comparison for a `Cc<T>` and a `T`.
+    ///
+    /// The inner value is compared with the other value by calling
+    /// `partial_cmp()`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let five = Cc::new(5);
+    ///
+    /// assert!(five < 6);
+    /// ```
+    #[inline(always)]
+    fn partial_cmp(&self, other: &T) -> Option<Ordering> {
+        (**self).partial_cmp(other)
+    }
+}
+
+// The comparisons the other way around, `T == Cc<T>`, can't be written for
+// every `T`, since the impl would be for a type this crate doesn't own. These
+// cover the primitive types instead.
+macro_rules! cc_cmp_reversed {
+    ($($t:ty),*) => {
+        $(
+            impl<A: 'static + Allocator> PartialEq<Cc<$t, A>> for $t {
+                #[inline(always)]
+                fn eq(&self, other: &Cc<$t, A>) -> bool { *self == **other }
+            }
+
+            impl<A: 'static + Allocator> PartialOrd<Cc<$t, A>> for $t {
+                #[inline(always)]
+                fn partial_cmp(&self, other: &Cc<$t, A>) -> Option<Ordering> {
+                    (*self).partial_cmp(&**other)
+                }
+            }
+        )*
+    }
+}
+
+cc_cmp_reversed!(bool, char, f32, f64,
+                 i8, i16, i32, i64, isize,
+                 u8, u16, u32, u64, usize,
+                 str, String);
+
+// Summing or multiplying the values behind an iterator of `&Cc<T>`. As with
+// the comparisons above, this can only be written for specific types.
+macro_rules! cc_sum_product {
+    ($($t:ty),*) => {
+        $(
+            impl<'a, A: 'static + Allocator> Sum<&'a Cc<$t, A>> for $t {
+                fn sum<I: Iterator<Item=&'a Cc<$t, A>>>(iter: I) -> $t {
+                    iter.map(|x| **x).sum()
+                }
+            }
+
+            impl<'a, A: 'static + Allocator> Product<&'a Cc<$t, A>> for $t {
+                fn product<I: Iterator<Item=&'a Cc<$t, A>>>(iter: I) -> $t {
+                    iter.map(|x| **x).product()
+                }
+            }
+        )*
+    }
+}
+
+cc_sum_product!(f32, f64,
+                i8, i16, i32, i64, isize,
+                u8, u16, u32, u64, usize);
+
+impl<T: 'static + CcTrace + Ord + ?Sized, A: 'static + Allocator> Ord for Cc<T, A> {
+    /// Comparison for two `Cc<T>`s.
+    ///
+    /// The two are compared by calling `cmp()` on their inner values.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let five = Cc::new(5);
+    ///
+    /// five.partial_cmp(&Cc::new(5));
+    /// ```
+    #[inline]
+    fn cmp(&self, other: &Cc<T, A>) -> Ordering { (**self).cmp(&**other) }
+}
+
+impl<T: 'static + CcTrace + Hash + ?Sized, A: 'static + Allocator> Hash for Cc<T, A> {
+    /// Hashes the inner value.
+    ///
+    /// Like equality, this only depends on the value, so two `Cc<T>`s that are
+    /// equal hash the same, even if they point to different allocations. Use
+    /// `CcAddress` to hash by allocation instead.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashSet;
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let mut set = HashSet::new();
+    /// set.insert(Cc::new(5));
+    /// assert!(set.contains(&Cc::new(5)));
+    /// ```
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state);
+    }
+}
+
+impl<T: 'static + CcTrace + fmt::Display + ?Sized, A: 'static + Allocator> fmt::Display for Cc<T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl<T: 'static + CcTrace + fmt::Debug + ?Sized, A: 'static + Allocator> fmt::Debug for Cc<T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: 'static + CcTrace + ?Sized, A: 'static + Allocator> fmt::Pointer for Cc<T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Pointer::fmt(&*self._ptr, f)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: 'static + CcTrace + Error + ?Sized, A: 'static + Allocator> Error for Cc<T, A> {
+    fn description(&self) -> &str {
+        (**self).description()
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        (**self).cause()
+    }
+
+    fn source(&self) -> Option<&(Error + 'static)> {
+        (**self).source()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: 'static + CcTrace + io::Read, A: 'static + Allocator> io::Read for Cc<RefCell<R>, A> {
+    /// Reads from the shared reader.
+    ///
+    /// If the reader is already borrowed, for example by a read further up
+    /// the stack, this fails with `io::ErrorKind::WouldBlock` instead of
+    /// panicking.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::cell::RefCell;
+    /// use std::io::Read;
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let mut reader = Cc::new(RefCell::new(&b"hello"[..]));
+    /// let mut buf = [0; 5];
+    /// reader.read_exact(&mut buf).unwrap();
+    /// assert_eq!(&buf, b"hello");
+    /// ```
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.try_borrow_mut() {
+            Ok(mut reader) => reader.read(buf),
+            Err(_) => Err(io::Error::new(io::ErrorKind::WouldBlock,
+                                         "the reader is already borrowed")),
+        }
+    }
+}
+
+/// A `Cc<T>` that is compared and hashed by the address of its allocation,
+/// rather than by its value.
+///
+/// This is useful for keeping track of which boxes have already been seen,
+/// for example in a set of visited nodes while walking a graph.
+///
+/// # Examples
+///
+/// ```
+/// use std::collections::HashSet;
+/// use bacon_rajan_cc::{Cc, CcAddress};
+///
+/// let five = Cc::new(5);
+///
+/// let mut seen = HashSet::new();
+/// assert!(seen.insert(CcAddress(five.clone())));
+/// assert!(!seen.insert(CcAddress(five.clone())));
+/// assert!(seen.insert(CcAddress(Cc::new(5))));
+/// ```
+pub struct CcAddress<T: 'static + CcTrace + ?Sized>(pub Cc<T>);
+
+impl<T: 'static + CcTrace + ?Sized> CcAddress<T> {
+    #[inline]
+    fn addr(&self) -> usize {
+        *self.0._ptr as *const u8 as usize
+    }
+}
+
+impl<T: 'static + CcTrace + ?Sized> Clone for CcAddress<T> {
+    #[inline]
+    fn clone(&self) -> CcAddress<T> { CcAddress(self.0.clone()) }
+}
+
+impl<T: 'static + CcTrace + ?Sized> PartialEq for CcAddress<T> {
+    #[inline]
+    fn eq(&self, other: &CcAddress<T>) -> bool { Cc::ptr_eq(&self.0, &other.0) }
+}
+
+impl<T: 'static + CcTrace + ?Sized> Eq for CcAddress<T> {}
+
+impl<T: 'static + CcTrace + ?Sized> Hash for CcAddress<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Cc::ptr_hash(&self.0, state);
+    }
+}
+
+impl<T: 'static + CcTrace + ?Sized> PartialOrd for CcAddress<T> {
+    #[inline]
+    fn partial_cmp(&self, other: &CcAddress<T>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: 'static + CcTrace + ?Sized> Ord for CcAddress<T> {
+    /// Orders by the address of the allocation, which doesn't depend on the
+    /// value, and doesn't change for as long as the `CcAddress` is alive.
+    #[inline]
+    fn cmp(&self, other: &CcAddress<T>) -> Ordering { self.addr().cmp(&other.addr()) }
+}
+
+impl<T: 'static + CcTrace + ?Sized> fmt::Debug for CcAddress<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CcAddress({:p})", self.0)
+    }
+}
+
+/// A part of the value in a `Cc<T>`, made by `Cc::project`.
+///
+/// This holds a strong reference to the whole value, and derefs to the part.
+pub struct CcProjection<T: 'static + CcTrace + ?Sized, U: ?Sized, A: 'static + Allocator = Global> {
+    owner: Cc<T, A>,
+    // points into the value owned by `owner`.
+    ptr: *const U,
+}
+
+impl<T: 'static + CcTrace + ?Sized, U: ?Sized, A: 'static + Allocator> CcProjection<T, U, A> {
+    /// Returns the `Cc<T>` this is a part of.
+    #[inline]
+    pub fn owner(this: &CcProjection<T, U, A>) -> &Cc<T, A> {
+        &this.owner
+    }
+}
+
+impl<T: 'static + CcTrace + ?Sized, U: ?Sized, A: 'static + Allocator> Deref for CcProjection<T, U, A> {
+    type Target = U;
+
+    #[inline(always)]
+    fn deref(&self) -> &U {
+        // the value can't go away while `owner` is alive, and a `Cc` never
+        // hands out a mutable reference to it while it is shared.
+        unsafe { &*self.ptr }
+    }
+}
+
+impl<T: 'static + CcTrace + ?Sized, U: ?Sized, A: 'static + Allocator> Clone for CcProjection<T, U, A> {
+    #[inline]
+    fn clone(&self) -> CcProjection<T, U, A> {
+        CcProjection { owner: self.owner.clone(), ptr: self.ptr }
+    }
+}
+
+impl<T: 'static + CcTrace + ?Sized, U: fmt::Debug + ?Sized, A: 'static + Allocator> fmt::Debug for CcProjection<T, U, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// A weak version of `Cc<T>`.
+///
+/// Weak references do not count when determining if the inner value should be
+/// dropped.
+///
+/// See the [module level documentation](./) for more.
+pub struct Weak<T: 'static + CcTrace + ?Sized, A: 'static + Allocator = Global> {
+    // FIXME #12808: strange names to try to avoid interfering with
+    // field accesses of the contained type via Deref
+    _ptr: NonZero<*mut CcBox<T, A>>,
+}
+
+impl<T: 'static + CcTrace> Weak<T> {
+    /// Constructs a new `Weak<T>` that doesn't point to any value, so that
+    /// `upgrade` always returns `None`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Weak;
+    ///
+    /// let empty: Weak<i32> = Weak::new();
+    /// assert!(empty.upgrade().is_none());
+    /// ```
+    pub fn new() -> Weak<T> {
+        // There is no box to point to, so this doesn't allocate one: it points
+        // to an address no box can have, which every method checks for before
+        // looking at the box.
+        unsafe { Weak { _ptr: NonZero::new(EMPTY_WEAK as *mut CcBox<T>) } }
+    }
+}
+
+impl<T: 'static + CcTrace> Default for Weak<T> {
+    /// Constructs a new `Weak<T>` that doesn't point to any value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Weak;
+    ///
+    /// let empty: Weak<i32> = Default::default();
+    /// assert!(empty.upgrade().is_none());
+    /// ```
+    fn default() -> Weak<T> {
+        Weak::new()
+    }
+}
+
+impl<T: 'static + CcTrace + ?Sized, A: 'static + Allocator> Weak<T, A> {
+
+    /// Upgrades a weak reference to a strong reference.
+    ///
+    /// Upgrades the `Weak<T>` reference to an `Cc<T>`, if possible.
+    ///
+    /// Returns `None` if there were no strong references and the data was
+    /// destroyed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![feature(alloc)]
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let five = Cc::new(5);
+    ///
+    /// let weak_five = five.downgrade();
+    ///
+    /// let strong_five: Option<Cc<_>> = weak_five.upgrade();
+    /// ```
+    pub fn upgrade(&self) -> Option<Cc<T, A>> {
+        // Once the strong count reaches zero the value is dropped, or about to
+        // be, even though the allocation lives on for as long as there are
+        // weak references. The count never goes back up from zero, so this
+        // stays `None` for good. A box that the collector is freeing must not
+        // be resurrected either, though its count is not zero until its value
+        // has been dropped.
+        if self.is_empty() || self.strong() == 0 || self.color() == Color::White {
+            None
+        } else {
+            self.inc_strong();
+            Some(Cc { _ptr: self._ptr })
+        }
+    }
+
+    /// Upgrades the `Weak<T>` if the value is still alive, and otherwise
+    /// returns the `Cc<T>` made by `f`.
+    ///
+    /// The new `Cc<T>` is not stored anywhere, so this `Weak<T>` stays dead;
+    /// downgrade the result and put it back if that is what you want.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::{Cc, Weak};
+    ///
+    /// let mut cache: Weak<String> = Weak::new();
+    ///
+    /// let value = cache.upgrade_or_else(|| Cc::new("computed".to_string()));
+    /// cache = value.downgrade();
+    /// assert!(Cc::ptr_eq(&cache.upgrade_or_else(|| unreachable!()), &value));
+    /// ```
+    #[inline]
+    pub fn upgrade_or_else<F: FnOnce() -> Cc<T, A>>(&self, f: F) -> Cc<T, A> {
+        match self.upgrade() {
+            Some(cc) => cc,
+            None => f(),
+        }
+    }
+
+    /// Returns true if the value is gone, so that `upgrade` would return
+    /// `None`.
+    ///
+    /// This only reads the counts, without making and dropping a `Cc<T>`, so
+    /// it doesn't buffer the box as a possible root either.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let five = Cc::new(5);
+    /// let weak_five = five.downgrade();
+    /// assert!(!weak_five.is_expired());
+    ///
+    /// drop(five);
+    /// assert!(weak_five.is_expired());
+    /// ```
+    #[inline]
+    pub fn is_expired(&self) -> bool {
+        // As in `upgrade`, a box that the collector is freeing is as good as
+        // gone.
+        self.is_empty() || self.strong() == 0 || self.color() == Color::White
+    }
+
+    /// Returns true if the two `Weak<T>`s point to the same allocation.
+    ///
+    /// This works whether or not the value is still alive, and does not
+    /// upgrade either of them. All the `Weak<T>`s made by `Weak::new` point to
+    /// the same, empty, allocation.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::{Cc, Weak};
+    ///
+    /// let five = Cc::new(5);
+    /// let weak_five = five.downgrade();
+    /// let other_five = Cc::new(5);
+    ///
+    /// assert!(Weak::ptr_eq(&weak_five, &five.downgrade()));
+    /// assert!(!Weak::ptr_eq(&weak_five, &other_five.downgrade()));
+    /// ```
+    #[inline]
+    pub fn ptr_eq(this: &Weak<T, A>, other: &Weak<T, A>) -> bool {
+        *this._ptr as *const u8 == *other._ptr as *const u8
+    }
+
+    /// Returns true if the `Weak<T>` points to the same allocation as the
+    /// `Cc<T>`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::{Cc, Weak};
+    ///
+    /// let five = Cc::new(5);
+    /// let weak_five = five.downgrade();
+    ///
+    /// assert!(Weak::ptr_eq_cc(&weak_five, &five));
+    /// assert!(!Weak::ptr_eq_cc(&weak_five, &Cc::new(5)));
+    /// ```
+    #[inline]
+    pub fn ptr_eq_cc(this: &Weak<T, A>, other: &Cc<T, A>) -> bool {
+        *this._ptr as *const u8 == *other._ptr as *const u8
+    }
+
+    /// Get the number of strong references to the value.
+    ///
+    /// This is zero once the value is gone, in which case `upgrade` returns
+    /// `None`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let five = Cc::new(5);
+    /// let weak_five = five.downgrade();
+    /// assert_eq!(weak_five.strong_count(), 1);
+    ///
+    /// drop(five);
+    /// assert_eq!(weak_five.strong_count(), 0);
+    /// ```
+    #[inline]
+    pub fn strong_count(&self) -> usize {
+        // a box that the collector is freeing is already gone, even though
+        // its count has not reached zero yet.
+        if self.is_empty() || self.color() == Color::White { 0 } else { self.strong() }
+    }
+
+    /// Get the number of weak references to the value, including this one.
+    ///
+    /// This is zero for a `Weak<T>` made by `Weak::new`, which doesn't point
+    /// to any value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let five = Cc::new(5);
+    /// let weak_five = five.downgrade();
+    /// let _also_weak_five = weak_five.clone();
+    ///
+    /// assert_eq!(weak_five.weak_count(), 2);
+    /// ```
+    #[inline]
+    pub fn weak_count(&self) -> usize {
+        // the implicit weak reference held by the strong ones is only there
+        // until the value is dropped. A box whose value is still to be
+        // dropped, or is being dropped, is white, and still has it.
+        if self.is_empty() {
+            0
+        } else if self.strong() == 0 && self.color() != Color::White {
+            self.weak()
+        } else {
+            self.weak() - 1
+        }
+    }
+}
+
+impl<T, U, A> CoerceUnsized<Weak<U, A>> for Weak<T, A>
+    where T: 'static + CcTrace + ?Sized + Unsize<U>, U: 'static + CcTrace + ?Sized,
+          A: 'static + Allocator {}
+
+impl<T: 'static + CcTrace + ?Sized, A: 'static + Allocator> Unpin for Weak<T, A> {}
+
+impl<T: 'static + CcTrace + ?Sized, A: 'static + Allocator> Drop for Weak<T, A> {
+    /// Drops the `Weak<T>`.
+    ///
+    /// This will decrement the weak reference count.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![feature(alloc)]
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// {
+    ///     let five = Cc::new(5);
+    ///     let weak_five = five.downgrade();
+    ///
+    ///     // stuff
+    ///
+    ///     drop(weak_five); // explicit drop
+    /// }
+    /// {
+    ///     let five = Cc::new(5);
+    ///     let weak_five = five.downgrade();
+    ///
+    ///     // stuff
+    ///
+    /// } // implicit drop
+    /// ```
+    fn drop(&mut self) {
+        if self.is_empty() {
+            return;
+        }
+        // as for `Cc`, this only ever runs once per `Weak`.
+        self.dec_weak();
+        // the weak count starts at 1, and will only go to zero if all the
+        // strong pointers have disappeared.
+        if self.weak() == 0 && !self.buffered() {
+            unsafe { self.deallocate(); }
+        }
+    }
+}
+
+impl<T: 'static + CcTrace + ?Sized, A: 'static + Allocator> Clone for Weak<T, A> {
+
+    /// Makes a clone of the `Weak<T>`.
+    ///
+    /// This increases the weak reference count. This works the same once the
+    /// value is gone, and the clone doesn't upgrade either.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![feature(alloc)]
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let weak_five = Cc::new(5).downgrade();
+    ///
+    /// weak_five.clone();
+    /// ```
+    #[inline]
+    fn clone(&self) -> Weak<T, A> {
+        // The allocation is alive for as long as this `Weak` is, whatever the
+        // strong count, so the weak count is always there to increment. An
+        // empty `Weak` has no count, and its clones are empty too.
+        if !self.is_empty() {
+            self.inc_weak();
+        }
+        Weak { _ptr: self._ptr }
+    }
+}
+
+impl<T: 'static + CcTrace + ?Sized, A: 'static + Allocator> fmt::Debug for Weak<T, A> {
+    /// Prints `(Weak)`, whatever `T` is. Use `Weak::debug_value` to see the
+    /// value as well.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "(Weak)")
+    }
+}
+
+impl<T: 'static + CcTrace + fmt::Debug + ?Sized, A: 'static + Allocator> Weak<T, A> {
+    /// Returns something that formats as `Weak { value: ... }` while the value
+    /// is alive, and as `Weak { <dropped> }` once it is gone.
+    ///
+    /// `Debug for Weak<T>` can't do this itself: it would then need `T: Debug`,
+    /// and a fallback for other `T`s would overlap with it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bacon_rajan_cc::Cc;
+    ///
+    /// let five = Cc::new(5);
+    /// let weak_five = five.downgrade();
+    /// assert_eq!(format!("{:?}", weak_five.debug_value()), "Weak { value: 5 }");
+    ///
+    /// drop(five);
+    /// assert_eq!(format!("{:?}", weak_five.debug_value()), "Weak { <dropped> }");
+    /// ```
+    pub fn debug_value<'a>(&'a self) -> WeakDebugValue<'a, T, A> {
+        WeakDebugValue(self)
+    }
+}
+
+/// Formats a `Weak<T>` along with its value. See `Weak::debug_value`.
+pub struct WeakDebugValue<'a, T, A = Global>(&'a Weak<T, A>)
+    where T: 'static + CcTrace + fmt::Debug + ?Sized, A: 'static + Allocator;
+
+impl<'a, T: 'static + CcTrace + fmt::Debug + ?Sized, A: 'static + Allocator> fmt::Debug for WeakDebugValue<'a, T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Look at the value without upgrading, so that formatting doesn't
+        // touch the counts or buffer the box as a possible root.
+        if self.0.is_expired() {
+            write!(f, "Weak {{ <dropped> }}")
+        } else {
+            f.debug_struct("Weak").field("value", &&self.0.inner().value).finish()
+        }
+    }
+}
+
+/// Compares by allocation, like `Weak::ptr_eq`.
+///
+/// Unlike equality of `Cc<T>`s, this never looks at the value, which might
+/// already be gone. Two `Weak<T>`s are equal if they were downgraded from
+/// the same `Cc<T>`, or cloned from each other, even once it is dropped.
+///
+/// # Examples
+///
+/// ```
+/// use bacon_rajan_cc::Cc;
+///
+/// let five = Cc::new(5);
+/// assert!(five.downgrade() == five.downgrade());
+/// assert!(five.downgrade() != Cc::new(5).downgrade());
+/// ```
+impl<T: 'static + CcTrace + ?Sized, A: 'static + Allocator> PartialEq for Weak<T, A> {
+    #[inline]
+    fn eq(&self, other: &Weak<T, A>) -> bool { Weak::ptr_eq(self, other) }
+}
+
+impl<T: 'static + CcTrace + ?Sized, A: 'static + Allocator> Eq for Weak<T, A> {}
+
+/// Hashes the address of the allocation, so that it agrees with the
+/// identity-based equality of `Weak<T>`.
+impl<T: 'static + CcTrace + ?Sized, A: 'static + Allocator> Hash for Weak<T, A> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (*self._ptr as *const u8 as usize).hash(state);
+    }
+}
+
+/// A settable slot for a `Weak<T>`, for pointing an already created value back
+/// at another one, like a child at its parent.
+///
+/// The slot doesn't own what it points to, so it is traced as owning nothing.
+///
+/// # Examples
+///
+/// ```
+/// use bacon_rajan_cc::{Cc, CcTrace, Tracer, WeakSlot};
+///
+/// struct Node {
+///     parent: WeakSlot<Node>,
+///     children: Vec<Cc<Node>>,
+/// }
+///
+/// impl CcTrace for Node {
+///     fn trace(&self, tracer: &mut Tracer) {
+///         self.parent.trace(tracer);
+///         self.children.trace(tracer);
+///     }
+/// }
+///
+/// let child = Cc::new(Node { parent: WeakSlot::new(), children: Vec::new() });
+/// let parent = Cc::new(Node { parent: WeakSlot::new(), children: vec![child.clone()] });
+/// child.parent.set(&parent);
+///
+/// assert!(Cc::ptr_eq(&child.parent.get().unwrap(), &parent));
+/// drop(parent);
+/// assert!(child.parent.get().is_none());
+/// ```
+pub struct WeakSlot<T: 'static + CcTrace + ?Sized, A: 'static + Allocator = Global>(RefCell<Option<Weak<T, A>>>);
+
+impl<T: 'static + CcTrace + ?Sized, A: 'static + Allocator> WeakSlot<T, A> {
+    /// Makes an empty slot.
+    #[inline]
+    pub fn new() -> WeakSlot<T, A> {
+        WeakSlot(RefCell::new(None))
+    }
+
+    /// Points the slot at `cc`, replacing whatever it pointed at before.
+    #[inline]
+    pub fn set(&self, cc: &Cc<T, A>) {
+        *self.0.borrow_mut() = Some(cc.downgrade());
+    }
+
+    /// Empties the slot.
+    #[inline]
+    pub fn clear(&self) {
+        *self.0.borrow_mut() = None;
+    }
+
+    /// Upgrades what the slot points to, or returns `None` if it is empty or
+    /// the value is gone.
+    #[inline]
+    pub fn get(&self) -> Option<Cc<T, A>> {
+        self.0.borrow().as_ref().and_then(|w| w.upgrade())
+    }
+}
+
+impl<T: 'static + CcTrace + ?Sized, A: 'static + Allocator> Default for WeakSlot<T, A> {
+    #[inline]
+    fn default() -> WeakSlot<T, A> {
+        WeakSlot::new()
+    }
+}
+
+impl<T: 'static + CcTrace + fmt::Debug + ?Sized, A: 'static + Allocator> fmt::Debug for WeakSlot<T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let weak = self.0.borrow();
+        f.debug_tuple("WeakSlot").field(&weak.as_ref().map(Weak::debug_value)).finish()
+    }
+}
+
+/// The reference counts and collector state of a `CcBox`.
+///
+/// This is kept separate from the value so that it can be accessed without
+/// knowing the value's type.
+pub struct CcBoxMetadata {
+    strong: Cell<usize>,
+    weak: Cell<usize>,
+    buffered: Cell<bool>,
+    rooted: Cell<bool>,
+    color: Cell<Color>,
+}
+
+impl fmt::Debug for CcBoxMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CcBoxMetadata")
+            .field("strong", &self.strong.get())
+            .field("weak", &self.weak.get())
+            .field("buffered", &self.buffered.get())
+            .field("rooted", &self.rooted.get())
+            .field("color", &self.color.get())
+            .finish()
+    }
+}
+
+impl CcBoxMetadata {
+    // The metadata of a newly allocated box: one strong reference, plus the
+    // implicit weak reference owned by all the strong ones.
+    fn new() -> CcBoxMetadata {
+        CcBoxMetadata {
+            strong: Cell::new(1),
+            weak: Cell::new(1),
+            buffered: Cell::new(false),
+            rooted: Cell::new(false),
+            color: Cell::new(Color::Black),
+        }
+    }
+}
+
+/// A pointer to a `CcBox`, with the type of the value inside erased.
+///
+/// This is what a `Tracer` is given for each child, and it is what lets the
+/// collector walk a graph of boxes holding values of many different types.
+///
+/// Only this crate can implement it, and from outside the counts and the
+/// collector state can only be read.
+pub trait CcBoxPtr: box_ptr::CcBoxHooks {
+    /// Get this box's reference counts and collector state.
+    fn metadata(&self) -> &CcBoxMetadata;
+
+    /// Trace the value in this box, calling `tracer` with each `Cc` the value
+    /// owns.
+    fn trace(&self, tracer: &mut Tracer);
+
+    #[inline]
+    fn strong(&self) -> usize { self.metadata().strong.get() }
+
+    #[inline]
+    fn weak(&self) -> usize { self.metadata().weak.get() }
+
+    #[inline]
+    fn color(&self) -> Color { self.metadata().color.get() }
+
+    #[inline]
+    fn buffered(&self) -> bool { self.metadata().buffered.get() }
+
+    #[inline]
+    fn rooted(&self) -> bool { self.metadata().rooted.get() }
+}
+
+// The parts of a `CcBoxPtr` that only the crate itself may use. The traits are
+// public so that `CcBoxPtr` can name `CcBoxHooks`, but the module isn't, so
+// other crates can neither implement `CcBoxPtr` nor call these.
+mod box_ptr {
+    use alloc::boxed::Box;
+
+    use super::{count_overflow, increment_overflows, CcBoxPtr};
+    use color::Color;
+
+    pub trait CcBoxHooks {
+        // Get a pointer to this box that does not own a reference count, for
+        // the collector to hold on to.
+        fn box_ref(&self) -> Box<CcBoxPtr>;
+
+        // Drop the value in this box, without touching the counts or the
+        // allocation.
+        unsafe fn drop_value(&self);
+
+        // Free this box's allocation.
+        unsafe fn deallocate(&self);
+    }
+
+    pub trait CcBoxCounts: CcBoxPtr {
+        #[inline]
+        fn inc_strong(&self) {
+            // going back up from zero would resurrect a dropped value.
+            debug_assert!(self.strong() != 0);
+            let strong = self.strong();
+            if increment_overflows(strong) {
+                count_overflow();
+            }
+            self.metadata().strong.set(strong + 1);
+            // Incrementing the count means this box is definitely live, so it
+            // can no longer be part of a garbage cycle.
+            self.metadata().color.set(Color::Black);
+        }
+
+        #[inline]
+        fn dec_strong(&self) { self.metadata().strong.set(self.strong() - 1); }
+
+        #[inline]
+        fn inc_weak(&self) {
+            let weak = self.weak();
+            if increment_overflows(weak) {
+                count_overflow();
+            }
+            self.metadata().weak.set(weak + 1);
+        }
+
+        #[inline]
+        fn dec_weak(&self) { self.metadata().weak.set(self.weak() - 1); }
+    }
+
+    impl<P: CcBoxPtr + ?Sized> CcBoxCounts for P {}
+}
+
+impl<T: 'static + CcTrace + ?Sized, A: 'static + Allocator> CcBoxPtr for CcBox<T, A> {
+    #[inline(always)]
+    fn metadata(&self) -> &CcBoxMetadata {
+        &self.metadata
+    }
+
+    #[inline(always)]
+    fn trace(&self, tracer: &mut Tracer) {
+        self.value.trace(tracer);
+    }
+}
+
+impl<T: 'static + CcTrace + ?Sized, A: 'static + Allocator> CcBoxHooks for CcBox<T, A> {
+    fn box_ref(&self) -> Box<CcBoxPtr> {
+        unsafe {
+            Box::new(CcBoxRef {
+                _ptr: NonZero::new(self as *const CcBox<T, A> as *mut CcBox<T, A>)
+            })
+        }
+    }
+
+    unsafe fn drop_value(&self) {
+        drop_in_place(&self.value as *const T as *mut T);
+    }
+
+    unsafe fn deallocate(&self) {
+        let layout = Layout::for_value(self);
+        let alloc = ptr::read(&self.alloc);
+        alloc.deallocate(NonNull::new_unchecked(self as *const CcBox<T, A> as *mut u8), layout);
+    }
+}
+
+impl<T: 'static + CcTrace + ?Sized, A: 'static + Allocator> CcBoxPtr for Cc<T, A> {
+    #[inline(always)]
+    fn metadata(&self) -> &CcBoxMetadata { self.inner().metadata() }
+
+    #[inline(always)]
+    fn trace(&self, tracer: &mut Tracer) { self.inner().trace(tracer) }
+}
+
+impl<T: 'static + CcTrace + ?Sized, A: 'static + Allocator> CcBoxHooks for Cc<T, A> {
+    fn box_ref(&self) -> Box<CcBoxPtr> { self.inner().box_ref() }
+
+    unsafe fn drop_value(&self) { self.inner().drop_value() }
+
+    unsafe fn deallocate(&self) { self.inner().deallocate() }
+}
+
+impl<T: 'static + CcTrace + ?Sized, A: 'static + Allocator> CcBoxPtr for Weak<T, A> {
+    #[inline(always)]
+    fn metadata(&self) -> &CcBoxMetadata {
+        // An empty `Weak` has no box, but it can still be looked at through a
+        // `&CcBoxPtr`, so it reads as a box whose value is gone.
+        if self.is_empty() { &EMPTY_WEAK_METADATA.0 } else { self.inner().metadata() }
+    }
+
+    #[inline(always)]
+    fn trace(&self, tracer: &mut Tracer) {
+        // Once the value is gone, or being freed, there is nothing left to
+        // trace, and an empty `Weak` never had a value.
+        if self.is_expired() {
+            return;
+        }
+        self.inner().trace(tracer)
+    }
+}
+
+impl<T: 'static + CcTrace + ?Sized, A: 'static + Allocator> CcBoxHooks for Weak<T, A> {
+    fn box_ref(&self) -> Box<CcBoxPtr> { self.inner().box_ref() }
+
+    unsafe fn drop_value(&self) { self.inner().drop_value() }
+
+    unsafe fn deallocate(&self) { self.inner().deallocate() }
+}
+
+// The address of the box an empty `Weak` points to. No box can be allocated
+// there, since it would have to fit into a single byte.
+const EMPTY_WEAK: usize = !0;
+
+// What the collector state of an empty `Weak` reads as. Nothing ever changes
+// it: the counts of a box are only changed by the `Cc`s and `Weak`s that point
+// to it, which an empty `Weak` doesn't touch, and by the collector, which
+// leaves out any empty `Weak` a `trace` impl hands to its tracer.
+struct EmptyWeakMetadata(CcBoxMetadata);
+
+unsafe impl Sync for EmptyWeakMetadata {}
+
+static EMPTY_WEAK_METADATA: EmptyWeakMetadata = EmptyWeakMetadata(CcBoxMetadata {
+    strong: Cell::new(0),
+    weak: Cell::new(0),
+    buffered: Cell::new(false),
+    rooted: Cell::new(false),
+    color: Cell::new(Color::Black),
+});
+
+// Whether `s` is an empty `Weak`, which has no box.
+fn is_empty_weak(s: &CcBoxPtr) -> bool {
+    ptr::eq(s.metadata(), &EMPTY_WEAK_METADATA.0)
+}
+
+// A pointer to a `CcBox` that does not own any reference count. The roots
+// buffer and the collector use these to keep track of boxes.
+struct CcBoxRef<T: 'static + CcTrace + ?Sized, A: 'static + Allocator = Global> {
+    _ptr: NonZero<*mut CcBox<T, A>>,
+}
+
+impl<T: 'static + CcTrace + ?Sized, A: 'static + Allocator> CcBoxPtr for CcBoxRef<T, A> {
+    #[inline(always)]
+    fn metadata(&self) -> &CcBoxMetadata { self.inner().metadata() }
+
+    #[inline(always)]
+    fn trace(&self, tracer: &mut Tracer) { self.inner().trace(tracer) }
+}
+
+impl<T: 'static + CcTrace + ?Sized, A: 'static + Allocator> CcBoxHooks for CcBoxRef<T, A> {
+    fn box_ref(&self) -> Box<CcBoxPtr> { self.inner().box_ref() }
+
+    unsafe fn drop_value(&self) { self.inner().drop_value() }
+
+    unsafe fn deallocate(&self) { self.inner().deallocate() }
+}
+
+impl<T: 'static + CcTrace + ?Sized, A: 'static + Allocator> Cc<T, A> {
+    #[inline(always)]
+    fn inner(&self) -> &CcBox<T, A> {
+        unsafe {
+            // Safe to assume this here, as if it weren't true, we'd be breaking
+            // the contract anyway.
+            // This allows the null check to be elided in the destructor if we
+            // manipulated the reference count in the same function.
+            assume(!(*self._ptr as *const ()).is_null());
+            &(**self._ptr)
+        }
+    }
+}
+
+impl<T: 'static + CcTrace + ?Sized, A: 'static + Allocator> Weak<T, A> {
+    // Whether this `Weak` was made by `Weak::new`, and so has no box.
+    #[inline(always)]
+    fn is_empty(&self) -> bool {
+        *self._ptr as *const u8 as usize == EMPTY_WEAK
+    }
+
+    #[inline(always)]
+    fn inner(&self) -> &CcBox<T, A> {
+        unsafe {
+            // Safe to assume this here, as if it weren't true, we'd be breaking
+            // the contract anyway.
+            // This allows the null check to be elided in the destructor if we
+            // manipulated the reference count in the same function.
+            assume(!(*self._ptr as *const ()).is_null());
+            &(**self._ptr)
+        }
+    }
+}
+
+impl<T: 'static + CcTrace + ?Sized, A: 'static + Allocator> CcBoxRef<T, A> {
+    #[inline(always)]
+    fn inner(&self) -> &CcBox<T, A> {
+        unsafe { &(**self._ptr) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![plugin(bacon_rajan_cc)]
+
+    use super::{Cc, CcAddress, CcBoxPtr, CcProjection, CcTrace, CollectStats, Tracer, Weak, WeakSlot};
+    use super::{weak_count, strong_count, ref_counts, debug_metadata};
+    use super::collect::{self, collect_cycles, collect_cycles_with_budget, collect_from};
+    use super::collect::{dump_graph_dot, dump_roots, set_auto_collect_threshold, trace_children, RootInfo};
+    use super::collect::{disable_auto_collect, with_collection_paused, CollectOnDrop};
+    use super::color::Color;
+    use std::boxed::Box;
+    use std::alloc::{AllocError, Allocator, Global, Layout};
+    use std::any::Any;
+    use std::cell::{Cell, RefCell};
+    use std::cmp::Ordering;
+    use std::collections::HashSet;
+    use std::collections::hash_map::DefaultHasher;
+    use std::error::Error;
+    use std::fmt;
+    use std::hash::{Hash, Hasher};
+    use std::io;
+    use std::marker::PhantomPinned;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::option::Option;
+    use std::option::Option::{Some, None};
+    use std::ptr::{self, NonNull};
+    use std::rc::Rc;
+    use std::result::Result::{Err, Ok};
+    use std::mem::{self, drop, MaybeUninit};
+    use std::clone::Clone;
+
+    // trace_macros!(true);
+
+    // #[derive(CcTrace, Debug)]
     // struct CycleCollected {
     //     a: Cc<u32>,
     //     b: Cc<String>,
     // }
 
-    // trace_macros!(false);
+    // trace_macros!(false);
+
+    // #[test]
+    // fn test_plugin() {
+    //     let x = CycleCollected {
+    //         a: Cc::new(5),
+    //         b: Cc::new("hello".into()),
+    //     };
+
+    //     CcTrace::trace(&x, &mut |v| {
+    //         println!("traced {:?}", v);
+    //     });
+
+    //     assert!(false);
+    // }
+
+    // Tests copied from `Rc<T>`.
+
+    #[test]
+    fn test_clone() {
+        let x = Cc::new(RefCell::new(5));
+        let y = x.clone();
+        *x.borrow_mut() = 20;
+        assert_eq!(*y.borrow(), 20);
+    }
+
+    #[test]
+    fn test_simple() {
+        let x = Cc::new(5);
+        assert_eq!(*x, 5);
+    }
+
+    #[test]
+    fn test_simple_clone() {
+        let x = Cc::new(5);
+        let y = x.clone();
+        assert_eq!(*x, 5);
+        assert_eq!(*y, 5);
+    }
+
+    #[test]
+    fn test_destructor() {
+        let x: Cc<Box<_>> = Cc::new(Box::new(5));
+        assert_eq!(**x, 5);
+    }
+
+    #[test]
+    fn test_live() {
+        let x = Cc::new(5);
+        let y = x.downgrade();
+        assert!(y.upgrade().is_some());
+    }
+
+    #[test]
+    fn test_dead() {
+        let x = Cc::new(5);
+        let y = x.downgrade();
+        drop(x);
+        assert!(y.upgrade().is_none());
+    }
+
+    #[test]
+    fn weak_self_cyclic() {
+        struct Cycle {
+            x: RefCell<Option<Weak<Cycle>>>
+        }
+
+        impl CcTrace for Cycle {
+            fn trace(&self, _tracer: &mut Tracer) { }
+        }
+
+        let a = Cc::new(Cycle { x: RefCell::new(None) });
+        let b = a.clone().downgrade();
+        *a.x.borrow_mut() = Some(b);
+
+        // hopefully we don't double-free (or leak)...
+    }
+
+    #[test]
+    fn test_increment_overflows() {
+        assert!(!super::increment_overflows(0));
+        assert!(!super::increment_overflows(1));
+        assert!(!super::increment_overflows(usize::max_value() - 1));
+        assert!(super::increment_overflows(usize::max_value()));
+
+        // Counting up to the largest count is fine.
+        let x = Cc::new(5);
+        let w = x.downgrade();
+        x.metadata().strong.set(usize::max_value() - 1);
+        x.metadata().weak.set(usize::max_value() - 1);
+        mem::forget(x.clone());
+        mem::forget(w.clone());
+        assert_eq!(x.strong(), usize::max_value());
+        assert_eq!(x.weak(), usize::max_value());
+        x.metadata().strong.set(1);
+        x.metadata().weak.set(2);
+    }
+
+    #[test]
+    fn test_new_with_weak() {
+        let (x, w) = Cc::new_with_weak(Box::new(5));
+        assert!(Weak::ptr_eq_cc(&w, &x));
+        assert!(Cc::ptr_eq(&w.upgrade().unwrap(), &x));
+        assert_eq!(ref_counts(&x), (1, 1));
+
+        drop(x);
+        assert!(w.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_new_cyclic() {
+        struct Cycle {
+            me: Weak<Cycle>,
+            upgraded_early: bool,
+        }
+
+        impl CcTrace for Cycle {
+            fn trace(&self, _tracer: &mut Tracer) { }
+        }
+
+        let a = Cc::new_cyclic(|me| {
+            Cycle { me: me.clone(), upgraded_early: me.upgrade().is_some() }
+        });
+        assert!(!a.upgraded_early);
+        assert_eq!(strong_count(&a), 1);
+        assert_eq!(weak_count(&a), 1);
+
+        let b = a.me.upgrade().unwrap();
+        assert!(Cc::ptr_eq(&a, &b));
+        assert_eq!(strong_count(&a), 2);
+    }
+
+    #[test]
+    fn has_sole_strong() {
+        let x = Cc::new(3);
+        assert!(super::has_sole_strong(&x));
+        let w = x.downgrade();
+        assert!(super::has_sole_strong(&x));
+        assert!(!super::is_unique(&x));
+        let y = w.upgrade().unwrap();
+        assert!(!super::has_sole_strong(&x));
+        drop(y);
+        assert!(super::has_sole_strong(&x));
+        drop(w);
+        assert!(super::has_sole_strong(&x));
+        assert!(super::is_unique(&x));
+    }
+
+    #[test]
+    fn is_unique() {
+        let x = Cc::new(3);
+        assert!(super::is_unique(&x));
+        let y = x.clone();
+        assert!(!super::is_unique(&x));
+        drop(y);
+        assert!(super::is_unique(&x));
+        let w = x.downgrade();
+        assert!(!super::is_unique(&x));
+        drop(w);
+        assert!(super::is_unique(&x));
+    }
+
+    #[test]
+    fn test_strong_count() {
+        let a = Cc::new(0u32);
+        assert!(strong_count(&a) == 1);
+        let w = a.downgrade();
+        assert!(strong_count(&a) == 1);
+        let b = w.upgrade().expect("upgrade of live rc failed");
+        assert!(strong_count(&b) == 2);
+        assert!(strong_count(&a) == 2);
+        drop(w);
+        drop(a);
+        assert!(strong_count(&b) == 1);
+        let c = b.clone();
+        assert!(strong_count(&b) == 2);
+        assert!(strong_count(&c) == 2);
+    }
+
+    #[test]
+    fn test_weak_count() {
+        let a = Cc::new(0u32);
+        assert!(strong_count(&a) == 1);
+        assert!(weak_count(&a) == 0);
+        let w = a.downgrade();
+        assert!(strong_count(&a) == 1);
+        assert!(weak_count(&a) == 1);
+        drop(w);
+        assert!(strong_count(&a) == 1);
+        assert!(weak_count(&a) == 0);
+        let c = a.clone();
+        assert!(strong_count(&a) == 2);
+        assert!(weak_count(&a) == 0);
+        drop(c);
+    }
+
+    #[test]
+    fn try_unwrap() {
+        let x = Cc::new(3);
+        assert_eq!(super::try_unwrap(x), Ok(3));
+        let x = Cc::new(4);
+        let _y = x.clone();
+        assert_eq!(super::try_unwrap(x), Err(Cc::new(4)));
+        let x = Cc::new(5);
+        let _w = x.downgrade();
+        assert_eq!(super::try_unwrap(x), Err(Cc::new(5)));
+    }
+
+    #[test]
+    fn get_mut() {
+        let mut x = Cc::new(3);
+        *super::get_mut(&mut x).unwrap() = 4;
+        assert_eq!(*x, 4);
+        let y = x.clone();
+        assert!(super::get_mut(&mut x).is_none());
+        drop(y);
+        assert!(super::get_mut(&mut x).is_some());
+        let _w = x.downgrade();
+        assert!(super::get_mut(&mut x).is_none());
+    }
+
+    #[test]
+    fn get_mut_unchecked() {
+        let mut x = Cc::new(vec![1u32]);
+        unsafe {
+            Cc::get_mut_unchecked(&mut x).push(2);
+        }
+        assert_eq!(*x, vec![1, 2]);
+        assert_eq!(strong_count(&x), 1);
+
+        let w = x.downgrade();
+        unsafe {
+            Cc::get_mut_unchecked(&mut x).push(3);
+        }
+        assert_eq!(*w.upgrade().unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_cowrc_clone_make_unique() {
+        let mut cow0 = Cc::new(75);
+        let mut cow1 = cow0.clone();
+        let mut cow2 = cow1.clone();
+
+        assert!(75 == *cow0.make_unique());
+        assert!(75 == *cow1.make_unique());
+        assert!(75 == *cow2.make_unique());
+
+        *cow0.make_unique() += 1;
+        *cow1.make_unique() += 2;
+        *cow2.make_unique() += 3;
+
+        assert!(76 == *cow0);
+        assert!(77 == *cow1);
+        assert!(78 == *cow2);
+
+        // none should point to the same backing memory
+        assert!(*cow0 != *cow1);
+        assert!(*cow0 != *cow2);
+        assert!(*cow1 != *cow2);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_cowrc_clone_unique2() {
+        let mut cow0 = Cc::new(75);
+        let cow1 = cow0.clone();
+        let cow2 = cow1.clone();
+
+        assert!(75 == *cow0);
+        assert!(75 == *cow1);
+        assert!(75 == *cow2);
+
+        *cow0.make_unique() += 1;
+
+        assert!(76 == *cow0);
+        assert!(75 == *cow1);
+        assert!(75 == *cow2);
+
+        // cow1 and cow2 should share the same contents
+        // cow0 should have a unique reference
+        assert!(*cow0 != *cow1);
+        assert!(*cow0 != *cow2);
+        assert!(*cow1 == *cow2);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_cowrc_clone_weak() {
+        let mut cow0 = Cc::new(75);
+        let cow1_weak = cow0.downgrade();
+
+        assert!(75 == *cow0);
+        assert!(75 == *cow1_weak.upgrade().unwrap());
+
+        *cow0.make_unique() += 1;
+
+        assert!(76 == *cow0);
+        assert!(cow1_weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_make_mut() {
+        let mut a = Cc::new(75);
+        let b = a.clone();
+        assert!(Cc::ptr_eq(&a, &b));
+
+        *a.make_mut() += 1;
+        assert!(!Cc::ptr_eq(&a, &b));
+        assert_eq!(*a, 76);
+        assert_eq!(*b, 75);
+        assert_eq!(strong_count(&a), 1);
+        assert_eq!(strong_count(&b), 1);
+
+        // Now unique, so it is mutated in place.
+        let ptr = Cc::as_ptr(&a);
+        *a.make_mut() += 1;
+        assert_eq!(Cc::as_ptr(&a), ptr);
+        assert_eq!(*a, 77);
+    }
+
+    #[test]
+    fn test_update() {
+        let mut a = Cc::new(vec![1, 2]);
+        let b = a.clone();
+        a.update(|v| v.push(3));
+        assert_eq!(*a, [1, 2, 3]);
+        assert_eq!(*b, [1, 2]);
+        assert!(!Cc::ptr_eq(&a, &b));
+
+        let ptr = Cc::as_ptr(&a);
+        a.update(|v| v[0] = 0);
+        assert_eq!(*a, [0, 2, 3]);
+        assert_eq!(Cc::as_ptr(&a), ptr);
+    }
+
+    #[test]
+    fn test_make_mut_weak() {
+        #[derive(PartialEq, Debug)]
+        struct NoClone(u32);
+
+        impl Clone for NoClone {
+            fn clone(&self) -> NoClone { panic!("cloned") }
+        }
+
+        impl CcTrace for NoClone {
+            fn trace(&self, _tracer: &mut Tracer) { }
+        }
+
+        let mut a = Cc::new(NoClone(75));
+        let w = a.downgrade();
+        let w2 = w.clone();
+
+        a.make_mut().0 += 1;
+        assert_eq!(*a, NoClone(76));
+        assert_eq!(strong_count(&a), 1);
+        assert_eq!(weak_count(&a), 0);
+        assert!(w.upgrade().is_none());
+        assert!(w2.upgrade().is_none());
+
+        // Dangling weak references can still be dropped.
+        drop(w);
+        drop(w2);
+        assert_eq!(*a, NoClone(76));
+    }
+
+    #[test]
+    fn test_make_mut_weak_buffered() {
+        disable_auto_collect();
+
+        let mut a = Cc::new(Box::new(75));
+        let w = a.downgrade();
+        drop(a.clone());
+        assert_eq!(collect::number_of_roots_buffered(), 1);
+
+        **a.make_mut() += 1;
+        assert_eq!(**a, 76);
+        assert!(w.upgrade().is_none());
+
+        // The old box is freed by the collector once the weak reference is gone.
+        drop(w);
+        collect_cycles();
+        assert_eq!(collect::number_of_roots_buffered(), 0);
+    }
+
+    #[test]
+    fn test_try_new() {
+        let x = Cc::try_new(Box::new(5)).ok().unwrap();
+        assert_eq!(**x, 5);
+        assert_eq!(strong_count(&x), 1);
+        assert_eq!(weak_count(&x), 0);
+    }
+
+    #[test]
+    fn test_try_new_failed_allocation() {
+        super::FAIL_ALLOCATIONS.with(|f| f.set(true));
+        let result = Cc::try_new(Box::new(5));
+        super::FAIL_ALLOCATIONS.with(|f| f.set(false));
+
+        match result {
+            Err(value) => assert_eq!(*value, 5),
+            Ok(_) => panic!("allocation should have failed"),
+        }
+    }
+
+    #[test]
+    fn test_cc_address() {
+        use std::collections::HashSet;
+
+        // Not `Hash` or `PartialEq` itself.
+        struct Opaque;
+
+        impl CcTrace for Opaque {
+            fn trace(&self, _tracer: &mut Tracer) { }
+        }
+
+        let a = Cc::new(Opaque);
+        let b = a.clone();
+        let c = Cc::new(Opaque);
+        assert!(CcAddress(a.clone()) == CcAddress(b.clone()));
+        assert!(CcAddress(a.clone()) != CcAddress(c.clone()));
+
+        let mut seen = HashSet::new();
+        assert!(seen.insert(CcAddress(a.clone())));
+        assert!(!seen.insert(CcAddress(b.clone())));
+        assert!(seen.insert(CcAddress(c.clone())));
+        assert_eq!(seen.len(), 2);
+
+        // Equal values in distinct boxes are distinct addresses.
+        let mut fives = HashSet::new();
+        fives.insert(CcAddress(Cc::new(5)));
+        fives.insert(CcAddress(Cc::new(5)));
+        assert_eq!(fives.len(), 2);
+    }
+
+    #[test]
+    fn test_clone_from() {
+        let a = Cc::new(5);
+        let mut b = a.clone();
+        b.clone_from(&a);
+        assert!(Cc::ptr_eq(&a, &b));
+        assert_eq!(strong_count(&a), 2);
+
+        let c = Cc::new(6);
+        b.clone_from(&c);
+        assert!(Cc::ptr_eq(&b, &c));
+        assert_eq!(strong_count(&a), 1);
+        assert_eq!(strong_count(&c), 2);
+
+        let mut d = Cc::new(7);
+        let w = d.downgrade();
+        d.clone_from(&c);
+        assert_eq!(*d, 6);
+        assert_eq!(strong_count(&c), 3);
+        assert!(w.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_weak_ptr_eq() {
+        let a = Cc::new(5);
+        let b = Cc::new(5);
+        let wa = a.downgrade();
+        let wb = b.downgrade();
+        assert!(Weak::ptr_eq(&wa, &wa.clone()));
+        assert!(Weak::ptr_eq(&wa, &a.downgrade()));
+        assert!(!Weak::ptr_eq(&wa, &wb));
+        assert!(Weak::ptr_eq_cc(&wa, &a));
+        assert!(!Weak::ptr_eq_cc(&wa, &b));
+
+        let mut children = vec![wa.clone(), wb.clone(), wa.clone()];
+        children.retain(|w| !Weak::ptr_eq(w, &wa));
+        assert_eq!(children.len(), 1);
+        assert!(Weak::ptr_eq_cc(&children[0], &b));
+
+        // Still comparable after the value is gone.
+        drop(a);
+        assert!(Weak::ptr_eq(&wa, &wa.clone()));
+        assert!(!Weak::ptr_eq(&wa, &wb));
+    }
+
+    #[test]
+    fn test_weak_hash_set() {
+        let a = Cc::new(5);
+        let b = Cc::new(5);
+        let wa = a.downgrade();
+
+        let mut observers = HashSet::new();
+        assert!(observers.insert(wa.clone()));
+        assert!(!observers.insert(wa.clone()));
+        assert!(!observers.insert(a.downgrade()));
+        assert!(observers.insert(b.downgrade()));
+        assert_eq!(observers.len(), 2);
+        assert_eq!(hash_of(&wa), hash_of(&a.downgrade()));
+
+        // Still found after the value is gone.
+        drop(a);
+        assert!(observers.contains(&wa));
+        assert!(observers.remove(&wa));
+        assert_eq!(observers.len(), 1);
+        assert!(!observers.contains(&wa));
+    }
+
+    #[test]
+    fn test_weak_debug() {
+        let a = Cc::new(5);
+        let w = a.downgrade();
+        assert_eq!(format!("{:?}", w), "(Weak)");
+        assert_eq!(format!("{:?}", w.debug_value()), "Weak { value: 5 }");
+        assert_eq!(strong_count(&a), 1);
+
+        drop(a);
+        assert_eq!(format!("{:?}", w.debug_value()), "Weak { <dropped> }");
+
+        // `T` doesn't need to be `Debug`.
+        struct Opaque;
+
+        impl CcTrace for Opaque {
+            fn trace(&self, _tracer: &mut Tracer) { }
+        }
+
+        let opaque = Cc::new(Opaque);
+        assert_eq!(format!("{:?}", opaque.downgrade()), "(Weak)");
+    }
+
+    #[test]
+    fn test_as_ptr() {
+        let x = Cc::new(5);
+        let y = x.clone();
+        assert_eq!(Cc::as_ptr(&x), Cc::as_ptr(&y));
+        assert_eq!(Cc::as_ptr(&x), &*x as *const u32);
+        assert_eq!(x.strong(), 2);
+
+        let z = Cc::new(5);
+        assert!(Cc::as_ptr(&x) != Cc::as_ptr(&z));
+
+        let s = Cc::from_str("hello");
+        assert_eq!(Cc::as_ptr(&s), &*s as *const str);
+    }
+
+    #[test]
+    fn test_pin() {
+        let x = Cc::pin(5);
+        assert_eq!(*x, 5);
+
+        let y = x.clone();
+        assert_eq!(*y, 5);
+        assert_eq!(format!("{:?}", y), "5");
+    }
+
+    #[test]
+    fn test_unpin() {
+        struct NotUnpin(PhantomPinned);
+
+        impl CcTrace for NotUnpin {
+            fn trace(&self, _tracer: &mut Tracer) { }
+        }
+
+        fn assert_unpin<T: Unpin>() { }
+
+        assert_unpin::<Cc<NotUnpin>>();
+        assert_unpin::<Weak<NotUnpin>>();
+        assert_unpin::<Cc<CcTrace>>();
+    }
+
+    #[test]
+    fn test_into_raw_from_raw() {
+        let x = Cc::new(Box::new(5));
+        let y = x.clone();
+        assert_eq!(strong_count(&x), 2);
+
+        let ptr = Cc::into_raw(y);
+        assert_eq!(ptr, Cc::as_ptr(&x));
+        assert_eq!(strong_count(&x), 2);
+
+        let y = unsafe { Cc::from_raw(ptr) };
+        assert!(Cc::ptr_eq(&x, &y));
+        assert_eq!(**y, 5);
+        assert_eq!(strong_count(&x), 2);
+
+        drop(y);
+        assert_eq!(strong_count(&x), 1);
+    }
+
+    #[test]
+    fn test_into_raw_from_raw_aligned() {
+        let x = Cc::new(5u8);
+        let y = unsafe { Cc::from_raw(Cc::into_raw(x)) };
+        assert_eq!(*y, 5);
+        assert_eq!(strong_count(&y), 1);
+
+        let x = Cc::new(5u64);
+        let y = unsafe { Cc::from_raw(Cc::into_raw(x)) };
+        assert_eq!(*y, 5);
+        assert_eq!(strong_count(&y), 1);
+    }
+
+    #[test]
+    fn test_count_methods() {
+        let a = Cc::new(0u32);
+        assert_eq!(a.strong_count(), strong_count(&a));
+        assert_eq!(a.weak_count(), weak_count(&a));
+
+        let b = a.clone();
+        let w = a.downgrade();
+        assert_eq!(a.strong_count(), 2);
+        assert_eq!(a.weak_count(), 1);
+        assert_eq!(b.strong_count(), strong_count(&a));
+        assert_eq!(b.weak_count(), weak_count(&a));
+
+        drop(w);
+        assert_eq!(a.weak_count(), 0);
+        assert_eq!(a.weak_count(), weak_count(&b));
+    }
+
+    trait DisplayTrace: fmt::Display + CcTrace {}
+
+    impl<T: fmt::Display + CcTrace> DisplayTrace for T {}
+
+    #[test]
+    fn test_coerce_unsized() {
+        let five = Cc::new(5u32);
+        let display: Cc<DisplayTrace> = five.clone();
+        assert_eq!(format!("{}", display), "5");
+        assert_eq!(strong_count(&five), 2);
+
+        let weak: Weak<DisplayTrace> = five.downgrade();
+        assert_eq!(format!("{}", weak.upgrade().unwrap()), "5");
+
+        let boxes: Vec<Cc<DisplayTrace>> = vec![Cc::new(1u8), Cc::new('a'),
+                                               Cc::from(String::from("b"))];
+        let strs: Vec<String> = boxes.iter().map(|x| x.to_string()).collect();
+        assert_eq!(strs, vec!["1", "a", "b"]);
+
+        drop(display);
+        assert_eq!(strong_count(&five), 1);
+    }
+
+    impl CcTrace for io::Error {
+        fn trace(&self, _tracer: &mut Tracer) { }
+    }
+
+    #[derive(Debug)]
+    struct Wrapped(io::Error);
+
+    impl fmt::Display for Wrapped {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "wrapped: {}", self.0)
+        }
+    }
+
+    impl Error for Wrapped {
+        fn description(&self) -> &str { "wrapped" }
+        fn cause(&self) -> Option<&Error> { Some(&self.0) }
+        fn source(&self) -> Option<&(Error + 'static)> { Some(&self.0) }
+    }
+
+    impl CcTrace for Wrapped {
+        fn trace(&self, _tracer: &mut Tracer) { }
+    }
+
+    #[test]
+    fn test_error() {
+        let err = Cc::new(io::Error::new(io::ErrorKind::Other, "oh no"));
+        let boxed: Box<Error> = Box::new(err.clone());
+        assert_eq!(boxed.description(), err.description());
+        assert_eq!(boxed.to_string(), "oh no");
+        assert!(boxed.cause().is_none());
+
+        let wrapped: Box<Error> = Box::new(Cc::new(Wrapped(io::Error::new(io::ErrorKind::Other,
+                                                                         "inner"))));
+        assert_eq!(wrapped.description(), "wrapped");
+        assert_eq!(wrapped.to_string(), "wrapped: inner");
+        assert_eq!(wrapped.cause().unwrap().to_string(), "inner");
+    }
+
+    #[test]
+    fn test_error_source() {
+        let err = Cc::new(io::Error::new(io::ErrorKind::Other, "oh no"));
+        assert!(err.source().is_none());
+
+        let wrapped = Cc::new(Wrapped(io::Error::new(io::ErrorKind::Other, "inner")));
+        let source = wrapped.source().unwrap();
+        assert!(ptr::eq(source as *const Error as *const u8,
+                        &wrapped.0 as *const io::Error as *const u8));
+        assert_eq!(source.to_string(), "inner");
+    }
+
+    fn hash_of<T: Hash + ?Sized>(value: &T) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn test_hash_matches_eq() {
+        let a = Cc::new(String::from("five"));
+        let b = Cc::new(String::from("five"));
+        assert!(!Cc::ptr_eq(&a, &b));
+        assert!(a == b);
+        assert_eq!(hash_of(&a), hash_of(&b));
+        assert_eq!(hash_of(&a), hash_of(&String::from("five")));
+
+        let mut set = HashSet::new();
+        set.insert(a.clone());
+        set.insert(b.clone());
+        assert_eq!(set.len(), 1);
+
+        // Splitting the allocation doesn't change the value, or its hash.
+        let mut c = a.clone();
+        c.make_mut();
+        assert!(!Cc::ptr_eq(&a, &c));
+        assert!(a == c);
+        assert_eq!(hash_of(&a), hash_of(&c));
+        set.insert(c);
+        assert_eq!(set.len(), 1);
+    }
+
+    #[test]
+    fn test_cc_address_ord() {
+        use std::collections::BTreeSet;
+
+        let five = Cc::new(5);
+        let other_five = Cc::new(5);
+        let six = Cc::new(6);
+
+        let mut set = BTreeSet::new();
+        assert!(set.insert(CcAddress(five.clone())));
+        assert!(!set.insert(CcAddress(five.clone())));
+        assert!(set.insert(CcAddress(other_five.clone())));
+        assert!(set.insert(CcAddress(six.clone())));
+        assert!(!set.insert(CcAddress(six.clone())));
+        assert_eq!(set.len(), 3);
+
+        let addrs: Vec<usize> = set.iter().map(|a| a.addr()).collect();
+        let mut sorted = addrs.clone();
+        sorted.sort();
+        assert_eq!(addrs, sorted);
+        assert_eq!(CcAddress(five.clone()).cmp(&CcAddress(five.clone())), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_project() {
+        struct Pair {
+            name: String,
+            value: Box<u32>,
+        }
+
+        impl CcTrace for Pair {
+            fn trace(&self, _tracer: &mut Tracer) {}
+        }
+
+        let pair = Cc::new(Pair { name: String::from("five"), value: Box::new(5) });
+        let name = Cc::project(&pair, |p| &p.name[..]);
+        let value = Cc::project(&pair, |p| &*p.value);
+        assert_eq!(pair.strong_count(), 3);
+        assert!(Cc::ptr_eq(CcProjection::owner(&name), &pair));
+
+        let w = pair.downgrade();
+        drop(pair);
+        assert_eq!(&*name, "five");
+        assert_eq!(*value, 5);
+        assert_eq!(format!("{:?}", value.clone()), "5");
+
+        drop(name);
+        drop(value);
+        assert!(w.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_ptr_eq() {
+        let x = Cc::new(5);
+        let y = x.clone();
+        assert!(Cc::ptr_eq(&x, &y));
+
+        let z = Cc::new(5);
+        assert!(x == z);
+        assert!(!Cc::ptr_eq(&x, &z));
+
+        let w = x.downgrade();
+        let upgraded = w.upgrade().unwrap();
+        assert!(Cc::ptr_eq(&x, &upgraded));
+        assert!(!Cc::ptr_eq(&z, &upgraded));
+    }
+
+    #[test]
+    fn test_ptr_hash() {
+        fn ptr_hash_of<T: CcTrace + ?Sized>(cc: &Cc<T>) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            Cc::ptr_hash(cc, &mut hasher);
+            hasher.finish()
+        }
+
+        let x = Cc::new(5);
+        let y = Cc::new(5);
+        assert_eq!(ptr_hash_of(&x), ptr_hash_of(&x.clone()));
+        assert!(ptr_hash_of(&x) != ptr_hash_of(&y));
+        assert_eq!(ptr_hash_of(&x), hash_of(&CcAddress(x.clone())));
+
+        let s: Cc<str> = Cc::from_str("hello");
+        assert_eq!(ptr_hash_of(&s), ptr_hash_of(&s.clone()));
+    }
+
+    #[test]
+    fn test_ptr_or_value_eq() {
+        let x = Cc::new(5);
+        assert!(Cc::ptr_or_value_eq(&x, &x));
+        assert!(Cc::ptr_or_value_eq(&x, &x.clone()));
+        assert!(Cc::ptr_or_value_eq(&x, &Cc::new(5)));
+        assert!(!Cc::ptr_or_value_eq(&x, &Cc::new(6)));
+
+        let nan = Cc::new(::std::f64::NAN);
+        assert!(Cc::ptr_or_value_eq(&nan, &nan.clone()));
+        assert!(nan != nan.clone());
+        assert!(!Cc::ptr_or_value_eq(&nan, &Cc::new(::std::f64::NAN)));
+    }
+
+    #[test]
+    fn test_cmp_inner() {
+        let five = Cc::new(5);
+        assert!(five == 5);
+        assert!(5 == five);
+        assert!(five != 6);
+        assert!(6 != five);
+        assert!(five < 6);
+        assert!(4 < five);
+        assert!(five >= 5);
+        assert!(5 >= five);
+        assert_eq!(five.partial_cmp(&7), Some(Ordering::Less));
+        assert_eq!(7.partial_cmp(&five), Some(Ordering::Greater));
+
+        let s = Cc::from_str("hello");
+        assert!(s == *"hello");
+        assert!(*"hello" == s);
+        assert!(s == "hello");
+    }
+
+    #[test]
+    fn test_upgrade_or_else() {
+        let x = Cc::new(5);
+        let w = x.downgrade();
+        let y = w.upgrade_or_else(|| panic!("the value is alive"));
+        assert!(Cc::ptr_eq(&x, &y));
+        assert_eq!(strong_count(&x), 2);
+
+        drop(x);
+        drop(y);
+        let z = w.upgrade_or_else(|| Cc::new(6));
+        assert_eq!(*z, 6);
+        assert!(!Weak::ptr_eq_cc(&w, &z));
+        assert!(w.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_weak_is_expired() {
+        let x = Cc::new(5);
+        let y = x.clone();
+        let w = x.downgrade();
+        assert!(!w.is_expired());
+        drop(x);
+        assert!(!w.is_expired());
+        assert_eq!(collect::number_of_roots_buffered(), 1);
+        drop(y);
+        assert!(w.is_expired());
+        assert!(w.clone().is_expired());
+        assert!(Weak::<u32>::new().is_expired());
+    }
+
+    #[test]
+    fn test_trace_dead_weak() {
+        let x = Cc::new(vec![Cc::new(1)]);
+        let w = x.downgrade();
+        let mut children = 0;
+        CcBoxPtr::trace(&w, &mut |_| children += 1);
+        assert_eq!(children, 1);
+
+        // The `Vec` is gone, and must not be looked at.
+        drop(x);
+        CcBoxPtr::trace(&w, &mut |_| children += 1);
+        assert_eq!(children, 1);
+    }
+
+    #[test]
+    fn test_upgrade_after_strong_dropped() {
+        let x = Cc::new(Box::new(5));
+        let y = x.clone();
+        let w = x.downgrade();
+        drop(x);
+        drop(y);
+        for _ in 0..1000 {
+            assert!(w.upgrade().is_none());
+            assert_eq!(w.strong_count(), 0);
+        }
+        assert_eq!(w.weak_count(), 1);
+
+        // The box is still buffered, and the collector must not bring it back.
+        collect_cycles();
+        for _ in 0..1000 {
+            assert!(w.upgrade().is_none());
+        }
+    }
+
+    #[test]
+    fn test_downgrade_all() {
+        let xs = [Cc::new(1), Cc::new(2), Cc::new(3)];
+        let weaks = Cc::downgrade_all(&xs);
+        assert_eq!(weaks.len(), 3);
+        for (w, x) in weaks.iter().zip(&xs) {
+            assert!(Cc::ptr_eq(&w.upgrade().unwrap(), x));
+            assert_eq!(x.weak_count(), 1);
+        }
+
+        let odd = Cc::downgrade_all(xs.iter().filter(|x| ***x % 2 == 1));
+        assert_eq!(odd.len(), 2);
+        assert_eq!(*odd[1].upgrade().unwrap(), 3);
+        assert!(Cc::downgrade_all(&[] as &[Cc<u32>]).is_empty());
+    }
+
+    #[test]
+    fn test_downgrade_fully_qualified() {
+        struct Strong(u32);
+
+        impl CcTrace for Strong {
+            fn trace(&self, _tracer: &mut Tracer) {}
+        }
+
+        impl Strong {
+            fn downgrade(&self) -> u32 { self.0 }
+        }
+
+        let x = Cc::new(Strong(5));
+        let w: Weak<Strong> = Cc::downgrade(&x);
+        assert_eq!(x.weak_count(), 1);
+        assert_eq!(w.upgrade().unwrap().0, 5);
+        assert_eq!(Strong::downgrade(&x), 5);
+    }
+
+    #[test]
+    fn test_try_unwrap_weak_accounting() {
+        // A weak reference that is gone by the time of the unwrap doesn't
+        // count.
+        let x = Cc::new(Box::new(3));
+        drop(x.downgrade());
+        assert_eq!(ref_counts(&x), (1, 0));
+        assert_eq!(super::try_unwrap(x), Ok(Box::new(3)));
+
+        // A live one does, and the unwrap gives the `Cc` back untouched.
+        let x = Cc::new(Box::new(4));
+        let w = x.downgrade();
+        let x = super::try_unwrap(x).unwrap_err();
+        assert_eq!(ref_counts(&x), (1, 1));
+        drop(w);
+        assert_eq!(super::try_unwrap(x), Ok(Box::new(4)));
+
+        // Unwrapping the last of several strong references.
+        let x = Cc::new(Box::new(5));
+        let y = x.clone();
+        let w = y.downgrade();
+        let x = super::try_unwrap(x).unwrap_err();
+        drop(y);
+        drop(w);
+        assert_eq!(ref_counts(&x), (1, 0));
+        assert_eq!(super::try_unwrap(x), Ok(Box::new(5)));
+        collect_cycles();
+
+        // After `make_mut` moved the value away from a weak reference, that
+        // weak reference belongs to the old box only.
+        let mut x = Cc::new(Box::new(6));
+        let w = x.downgrade();
+        **x.make_mut() = 7;
+        assert_eq!(ref_counts(&x), (1, 0));
+        assert_eq!(w.strong_count(), 0);
+        assert_eq!(w.weak_count(), 1);
+        let w2 = x.downgrade();
+        let x = super::try_unwrap(x).unwrap_err();
+        drop(w2);
+        assert_eq!(super::try_unwrap(x), Ok(Box::new(7)));
+        assert!(w.upgrade().is_none());
+        drop(w);
+
+        // Mutating through `get_mut` doesn't touch the counts.
+        let mut x = Cc::new(Box::new(8));
+        **super::get_mut(&mut x).unwrap() = 9;
+        let w = x.downgrade();
+        assert!(super::get_mut(&mut x).is_none());
+        drop(w);
+        assert_eq!(super::try_unwrap(x), Ok(Box::new(9)));
+    }
+
+    #[test]
+    fn test_into_inner() {
+        let x = Cc::new(Box::new(3));
+        assert_eq!(Cc::into_inner(x), Some(Box::new(3)));
+
+        // Outstanding weak references don't get in the way, and can't be
+        // upgraded afterwards.
+        let x = Cc::new(Box::new(4));
+        let w = x.downgrade();
+        let w2 = w.clone();
+        assert_eq!(Cc::into_inner(x), Some(Box::new(4)));
+        assert!(w.upgrade().is_none());
+        assert_eq!(w.strong_count(), 0);
+        assert_eq!(w.weak_count(), 2);
+        drop(w);
+        assert!(w2.upgrade().is_none());
+        drop(w2);
+
+        // Another strong reference does, and the `Cc` is dropped.
+        let x = Cc::new(Box::new(5));
+        let y = x.clone();
+        assert_eq!(Cc::into_inner(x), None);
+        assert_eq!(y.strong_count(), 1);
+        let w = y.downgrade();
+        assert_eq!(Cc::into_inner(y), Some(Box::new(5)));
+        assert!(w.upgrade().is_none());
+        drop(w);
+        collect_cycles();
+    }
+
+    #[test]
+    fn test_unwrap_or_clone() {
+        struct Counted(Rc<Cell<usize>>);
+
+        impl Clone for Counted {
+            fn clone(&self) -> Counted {
+                self.0.set(self.0.get() + 1);
+                Counted(self.0.clone())
+            }
+        }
+
+        impl CcTrace for Counted {
+            fn trace(&self, _tracer: &mut Tracer) {}
+        }
+
+        let clones = Rc::new(Cell::new(0));
+
+        // Unique: the value is moved out, and nothing is cloned.
+        let x = Cc::new(Counted(clones.clone()));
+        let _v = Cc::unwrap_or_clone(x);
+        assert_eq!(clones.get(), 0);
+
+        // Shared: the value is cloned, and the `Cc` released.
+        let x = Cc::new(Counted(clones.clone()));
+        let y = x.clone();
+        let w = x.downgrade();
+        let _v = Cc::unwrap_or_clone(x);
+        assert_eq!(clones.get(), 1);
+        assert_eq!(y.strong_count(), 1);
+        let _v = Cc::unwrap_or_clone(y);
+        assert_eq!(clones.get(), 2);
+        assert!(w.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_debug_metadata() {
+        let x = Cc::new(5);
+        assert_eq!(debug_metadata(&x),
+                   "CcBoxMetadata { strong: 1, weak: 1, buffered: false, rooted: false, color: Black }");
+
+        let y = x.clone();
+        let _w = x.downgrade();
+        drop(y);
+        let metadata = debug_metadata(&x);
+        assert!(metadata.contains("strong: 1"));
+        assert!(metadata.contains("weak: 2"));
+        assert!(metadata.contains("buffered: true"));
+        assert!(metadata.contains("color: Purple"));
+
+        collect_cycles();
+        let metadata = debug_metadata(&x);
+        assert!(metadata.contains("buffered: false"));
+        assert!(metadata.contains("color: Black"));
+    }
+
+    #[test]
+    fn test_ref_counts() {
+        let x = Cc::new(5);
+        assert_eq!(ref_counts(&x), (1, 0));
+        let y = x.clone();
+        let w = x.downgrade();
+        let w2 = y.downgrade();
+        assert_eq!(ref_counts(&x), (strong_count(&x), weak_count(&x)));
+        assert_eq!(ref_counts(&y), (2, 2));
+        drop(w);
+        drop(y);
+        assert_eq!(ref_counts(&x), (1, 1));
+        drop(w2);
+        assert_eq!(ref_counts(&x), (1, 0));
+    }
+
+    #[test]
+    fn test_weak_new() {
+        let w: Weak<Box<i32>> = Weak::new();
+        assert!(w.upgrade().is_none());
+        assert_eq!(w.strong_count(), 0);
+        assert_eq!(w.weak_count(), 0);
+        assert!(w.is_expired());
+        let w2 = w.clone();
+        assert!(w2.upgrade().is_none());
+        assert!(Weak::ptr_eq(&w, &w2));
+        assert_eq!(format!("{:?}", w2.debug_value()), "Weak { <dropped> }");
+
+        // Through a `&CcBoxPtr`, it reads as a box whose value is gone.
+        let p: &CcBoxPtr = &w;
+        assert_eq!((p.strong(), p.weak()), (0, 0));
+        assert_eq!(collect_from(&[p]).objects_freed, 0);
+
+        // Unsizing keeps it empty.
+        let t: Weak<CcTrace> = w2;
+        assert!(t.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_empty_weak_traced() {
+        let mut children = 0;
+        CcBoxPtr::trace(&Weak::<Cc<i32>>::new(), &mut |_| children += 1);
+        assert_eq!(children, 0);
+
+        // A `trace` impl that hands an empty `Weak` to its tracer.
+        struct Linked {
+            next: RefCell<Option<Cc<Linked>>>,
+            empty: Weak<Linked>,
+        }
+
+        impl CcTrace for Linked {
+            fn trace(&self, tracer: &mut Tracer) {
+                if let Some(ref next) = *self.next.borrow() {
+                    tracer(next);
+                }
+                tracer(&self.empty);
+            }
+        }
+
+        let a = Cc::new(Linked { next: RefCell::new(None), empty: Weak::new() });
+        *a.next.borrow_mut() = Some(a.clone());
+        assert_eq!(trace_children(&*a), vec![a.metadata() as *const super::CcBoxMetadata as usize]);
+        assert_eq!(dump_graph_dot(&a).matches(" -> ").count(), 1);
+
+        // Collecting a live graph, and then a garbage one, never touches the
+        // empty `Weak`'s state, which all threads share.
+        let b = a.clone();
+        drop(b);
+        assert_eq!(collect_cycles().objects_freed, 0);
+        drop(a);
+        assert_eq!(collect_cycles().objects_freed, 1);
+        let empty = &super::EMPTY_WEAK_METADATA.0;
+        assert_eq!((empty.strong.get(), empty.weak.get()), (0, 0));
+        assert_eq!(empty.color.get(), Color::Black);
+    }
+
+    #[test]
+    fn test_weak_default() {
+        #[derive(Default)]
+        struct Parent {
+            name: String,
+            owner: Weak<String>,
+        }
+
+        let p = Parent::default();
+        assert_eq!(p.name, "");
+        assert!(p.owner.upgrade().is_none());
+        drop(p);
+        assert_eq!(collect::number_of_roots_buffered(), 0);
+    }
+
+    #[test]
+    fn test_weak_counts() {
+        let x = Cc::new(5);
+        let w = x.downgrade();
+        assert_eq!(w.strong_count(), 1);
+        assert_eq!(w.weak_count(), 1);
+
+        let y = x.clone();
+        let w2 = w.clone();
+        assert_eq!(w.strong_count(), 2);
+        assert_eq!(w.weak_count(), 2);
+
+        drop(y);
+        drop(x);
+        assert_eq!(w.strong_count(), 0);
+        assert_eq!(w.weak_count(), 2);
+        drop(w2);
+        assert_eq!(w.weak_count(), 1);
+        assert!(w.upgrade().is_none());
+        collect_cycles();
+    }
+
+    #[test]
+    fn test_weak_counts_collected_cycle() {
+        struct Node {
+            next: RefCell<Option<Cc<Node>>>,
+        }
+
+        impl CcTrace for Node {
+            fn trace(&self, tracer: &mut Tracer) {
+                self.next.trace(tracer);
+            }
+        }
+
+        let a = Cc::new(Node { next: RefCell::new(None) });
+        let b = Cc::new(Node { next: RefCell::new(Some(a.clone())) });
+        *a.next.borrow_mut() = Some(b.clone());
+        let w = a.downgrade();
+        drop(b);
+        drop(a);
+        assert_eq!(w.strong_count(), 1);
+        assert_eq!(w.weak_count(), 1);
+
+        collect_cycles();
+        assert_eq!(w.strong_count(), 0);
+        assert_eq!(w.weak_count(), 1);
+    }
+
+    #[test]
+    fn test_weak_counts_pending_drop() {
+        // Dropping `Parent` drops both siblings. The second one's value is
+        // dropped first, while the first one is still waiting for its turn.
+        struct Sibling {
+            other: Option<Weak<Sibling>>,
+            seen: Rc<Cell<Option<(usize, usize)>>>,
+        }
+
+        impl CcTrace for Sibling {
+            fn trace(&self, _tracer: &mut Tracer) { }
+        }
+
+        impl Drop for Sibling {
+            fn drop(&mut self) {
+                if let Some(ref other) = self.other {
+                    self.seen.set(Some((other.strong_count(), other.weak_count())));
+                }
+            }
+        }
+
+        struct Parent(Vec<Cc<Sibling>>);
+
+        impl CcTrace for Parent {
+            fn trace(&self, tracer: &mut Tracer) {
+                self.0.trace(tracer);
+            }
+        }
+
+        let seen = Rc::new(Cell::new(None));
+        let first = Cc::new(Sibling { other: None, seen: seen.clone() });
+        let second = Cc::new(Sibling { other: Some(first.downgrade()), seen: seen.clone() });
+        let w = first.downgrade();
+        drop(Cc::new(Parent(vec![first, second])));
+        assert_eq!(seen.get(), Some((0, 2)));
+        assert_eq!(w.weak_count(), 1);
+    }
+
+    #[test]
+    fn test_new_uninit() {
+        let mut x = Cc::<Box<u32>>::new_uninit();
+        let w = x.downgrade();
+        unsafe { Cc::get_mut_unchecked(&mut x).as_mut_ptr().write(Box::new(5)) };
+
+        let x = unsafe { x.assume_init() };
+        assert_eq!(**x, 5);
+        assert_eq!(x.strong_count(), 1);
+        assert_eq!(x.weak_count(), 1);
+        assert!(w.upgrade().is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity overflow")]
+    fn test_new_uninit_slice_overflow() {
+        Cc::<u64>::new_uninit_slice(usize::max_value() / 4);
+    }
+
+    #[test]
+    fn test_new_uninit_slice() {
+        let mut x = Cc::<Box<u32>>::new_uninit_slice(4);
+        assert_eq!(x.len(), 4);
+        for (i, elem) in super::get_mut(&mut x).unwrap().iter_mut().enumerate() {
+            *elem = MaybeUninit::new(Box::new(i as u32 * 10));
+        }
+
+        let x = unsafe { x.assume_init() };
+        assert_eq!(x.len(), 4);
+        assert_eq!(*x[0], 0);
+        assert_eq!(*x[3], 30);
+        let y = x.clone();
+        drop(x);
+        assert_eq!(*y[2], 20);
+
+        let empty = unsafe { Cc::<u64>::new_uninit_slice(0).assume_init() };
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn test_read() {
+        use std::io::Read;
+
+        let mut reader = Cc::new(RefCell::new(&b"hello world"[..]));
+        let mut other = reader.clone();
+
+        let mut buf = [0; 5];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+
+        let mut rest = Vec::new();
+        other.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b" world");
+
+        let _borrow = reader.borrow_mut();
+        let err = other.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn test_leak() {
+        let x = Cc::new(Box::new(5));
+        let y = x.clone();
+        let leaked: &'static Box<i32> = Cc::leak(x);
+        assert_eq!(**leaked, 5);
+        assert_eq!(strong_count(&y), 2);
+
+        drop(y);
+        collect_cycles();
+        assert_eq!(**leaked, 5);
+
+        // Take the leaked reference back, so as not to leak in the tests.
+        drop(unsafe { Cc::from_raw(leaked as *const Box<i32>) });
+    }
+
+    #[test]
+    fn test_downcast() {
+        let x: Cc<Any> = Cc::new(Box::new(5u32));
+        let y = x.clone();
+        assert_eq!(strong_count(&x), 2);
+
+        // A failed downcast gives the same `Cc` back.
+        let x = x.downcast::<u32>().err().unwrap();
+        assert!(Cc::ptr_eq(&x, &y));
+        assert_eq!(strong_count(&x), 2);
+
+        let x: Cc<Box<u32>> = x.downcast().ok().unwrap();
+        assert_eq!(**x, 5);
+        assert_eq!(strong_count(&x), 2);
+        drop(y);
+        assert_eq!(strong_count(&x), 1);
+    }
+
+    #[test]
+    fn test_as_non_null() {
+        let x = Cc::new(5);
+        let ptr = Cc::as_non_null(&x);
+        assert_eq!(ptr.as_ptr() as *const i32, &*x as *const i32);
+        assert_eq!(ptr.as_ptr() as *const i32, Cc::as_ptr(&x));
+        assert_eq!(x.strong_count(), 1);
+
+        let s: Cc<str> = Cc::from_str("hello");
+        let ptr = Cc::as_non_null(&s);
+        assert_eq!(unsafe { ptr.as_ref() }, "hello");
+    }
+
+    #[test]
+    fn test_sum_product() {
+        let tallies = vec![Cc::new(1), Cc::new(2), Cc::new(3), Cc::new(4)];
+        let sum: i32 = tallies.iter().sum();
+        let product: i32 = tallies.iter().product();
+        assert_eq!(sum, 10);
+        assert_eq!(product, 24);
+
+        let empty: Vec<Cc<f64>> = Vec::new();
+        assert_eq!(empty.iter().sum::<f64>(), 0.0);
+        assert_eq!(empty.iter().product::<f64>(), 1.0);
+    }
+
+    #[test]
+    fn test_drop_moved_out_husk() {
+        struct Husk {
+            strong: Option<Cc<Box<i32>>>,
+            weak: Option<Weak<Box<i32>>>,
+            other: Cc<Box<i32>>,
+        }
+
+        let x = Cc::new(Box::new(5));
+        let mut husk = Husk {
+            strong: Some(x.clone()),
+            weak: Some(x.downgrade()),
+            other: x.clone(),
+        };
+        assert_eq!(ref_counts(&x), (3, 1));
+
+        // Moving out of the husk leaves nothing behind to be dropped again.
+        let strong = husk.strong.take().unwrap();
+        let weak = husk.weak.take().unwrap();
+        let other = mem::replace(&mut husk.other, Cc::new(Box::new(6)));
+        drop(husk);
+        assert_eq!(ref_counts(&x), (3, 1));
+
+        drop(strong);
+        drop(weak);
+        drop(other);
+        assert_eq!(ref_counts(&x), (1, 0));
+
+        // Moving the fields out by destructuring.
+        let husk = Husk { strong: Some(x.clone()), weak: None, other: x.clone() };
+        let Husk { strong, other, .. } = husk;
+        assert_eq!(ref_counts(&x), (3, 0));
+        drop(strong);
+        drop(other);
+        assert_eq!(super::try_unwrap(x), Ok(Box::new(5)));
+        collect_cycles();
+    }
+
+    #[test]
+    fn test_increment_decrement_strong_count() {
+        let x = Cc::new(Box::new(5));
+        let w = x.downgrade();
+        let ptr = Cc::as_ptr(&x);
+        unsafe {
+            Cc::increment_strong_count(ptr);
+            Cc::increment_strong_count(ptr);
+        }
+        assert_eq!(ref_counts(&x), (3, 1));
+        unsafe { Cc::decrement_strong_count(ptr); }
+        assert_eq!(ref_counts(&x), (2, 1));
+
+        // The last strong reference is released through the raw pointer.
+        drop(x);
+        assert_eq!(w.strong_count(), 1);
+        unsafe { Cc::decrement_strong_count(ptr); }
+        assert!(w.upgrade().is_none());
+        drop(w);
+
+        let y = Cc::new(Box::new(6));
+        let ptr = Cc::into_raw(y.clone());
+        unsafe { Cc::increment_strong_count(ptr); }
+        assert_eq!(y.strong_count(), 3);
+        unsafe {
+            Cc::decrement_strong_count(ptr);
+            drop(Cc::from_raw(ptr));
+        }
+        assert_eq!(y.strong_count(), 1);
+        collect_cycles();
+    }
+
+    #[test]
+    fn test_clone_ref() {
+        let x: Cc<Vec<u8>> = Cc::new(vec![1, 2, 3]);
+        let y = Cc::clone_ref(&x);
+        assert!(Cc::ptr_eq(&x, &y));
+        assert_eq!(x.as_ptr(), y.as_ptr());
+        assert_eq!(x.strong_count(), 2);
+    }
+
+    #[test]
+    fn test_from_vec() {
+        let x: Cc<[Box<i32>]> = Cc::from(vec![Box::new(1), Box::new(2), Box::new(3)]);
+        assert_eq!(x.len(), 3);
+        assert_eq!(*x[0], 1);
+        assert_eq!(*x[2], 3);
+
+        let empty: Cc<[Box<i32>]> = Cc::from(Vec::new());
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn test_from_boxed_slice() {
+        let b: Box<[String]> = vec![String::from("a"), String::from("b")].into_boxed_slice();
+        let x: Cc<[String]> = Cc::from(b);
+        assert_eq!(x.len(), 2);
+        assert_eq!(x[1], "b");
+    }
+
+    #[test]
+    fn test_from_slice() {
+        let x: Cc<[i32]> = Cc::from_slice(&[1, 2, 3]);
+        let y = x.clone();
+        assert_eq!(x.len(), 3);
+        assert_eq!(x[0], 1);
+        assert_eq!(y[2], 3);
+        assert_eq!(&*x, &*y);
+        assert!(Cc::ptr_eq(&x, &y));
+        assert_eq!(strong_count(&x), 2);
+    }
+
+    #[test]
+    fn test_slice_get() {
+        let x: Cc<[i32]> = Cc::from_slice(&[1, 2, 3]);
+        assert_eq!(x.get(0), Some(&1));
+        assert_eq!(x.get(2), Some(&3));
+        assert_eq!(x.get(3), None);
+        assert_eq!(x.get(usize::max_value()), None);
+        assert_eq!(x.len(), 3);
+        assert!(!x.is_empty());
+
+        let empty: Cc<[i32]> = Cc::from_slice(&[]);
+        assert_eq!(empty.get(0), None);
+        assert_eq!(empty.len(), 0);
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn test_from_slice_boxes() {
+        let x: Cc<[Box<u32>]> = Cc::from_slice(&[Box::new(1), Box::new(2)]);
+        let y = x.clone();
+        drop(x);
+        assert_eq!(*y[1], 2);
+    }
+
+    #[test]
+    fn test_from_slice_clone_panics() {
+        thread_local!(static DROPS: Cell<usize> = Cell::new(0));
+
+        struct PanicOnClone(Box<u32>);
+
+        impl CcTrace for PanicOnClone {
+            fn trace(&self, _tracer: &mut Tracer) { }
+        }
+
+        impl Clone for PanicOnClone {
+            fn clone(&self) -> PanicOnClone {
+                if *self.0 == 3 {
+                    panic!("PanicOnClone cloned");
+                }
+                PanicOnClone(self.0.clone())
+            }
+        }
+
+        impl Drop for PanicOnClone {
+            fn drop(&mut self) {
+                DROPS.with(|d| d.set(d.get() + 1));
+            }
+        }
+
+        let elems: Vec<PanicOnClone> = (1..5).map(|i| PanicOnClone(Box::new(i))).collect();
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| Cc::from_slice(&elems))).is_err());
+        // The two clones made before the panic are dropped again.
+        assert_eq!(DROPS.with(|d| d.get()), 2);
+        drop(elems);
+        assert_eq!(DROPS.with(|d| d.get()), 6);
+    }
+
+    #[test]
+    fn test_from_iter() {
+        let x: Cc<[u32]> = (0..10).collect();
+        assert_eq!(x.len(), 10);
+        assert_eq!(x[0], 0);
+        assert_eq!(x[9], 9);
+
+        let y: Cc<[Box<u32>]> = vec![Box::new(1), Box::new(2)].into_iter().collect();
+        assert_eq!(*y[1], 2);
+
+        let z: Cc<[u16]> = (0..10).map(|x| x * 2).filter(|x| x % 3 == 0).collect();
+        assert_eq!(&*z, &[0, 6, 12, 18]);
+    }
+
+    #[test]
+    fn test_into_iter_ref() {
+        let x: Cc<[u32]> = vec![1, 2, 3].into_iter().collect();
+        let mut seen = Vec::new();
+        for i in &x {
+            seen.push(*i);
+        }
+        assert_eq!(seen, vec![1, 2, 3]);
+        assert_eq!((&x).into_iter().rev().next(), Some(&3));
+
+        let empty: Cc<[u32]> = Cc::from_slice(&[]);
+        assert_eq!((&empty).into_iter().count(), 0);
+    }
+
+    #[test]
+    fn test_try_from_slice() {
+        use std::convert::TryFrom;
+
+        let x: Cc<[Box<u32>]> = vec![Box::new(1), Box::new(2), Box::new(3)].into_iter().collect();
+        let y = x.clone();
+        let a: Cc<[Box<u32>; 3]> = Cc::try_from(x).ok().unwrap();
+        assert_eq!(*a[2], 3);
+        assert_eq!(strong_count(&a), 2);
+        assert_eq!(strong_count(&y), 2);
+        assert_eq!(Cc::as_ptr(&a) as *const u8, Cc::as_ptr(&y) as *const u8);
+
+        drop(y);
+        assert_eq!(strong_count(&a), 1);
+    }
+
+    #[test]
+    fn test_try_from_slice_wrong_length() {
+        use std::convert::TryFrom;
+
+        let x: Cc<[u32]> = Cc::from_slice(&[1, 2, 3]);
+        let y = x.clone();
+        let x = match Cc::<[u32; 4]>::try_from(x) {
+            Ok(_) => panic!("length should not match"),
+            Err(x) => x,
+        };
+        assert!(Cc::ptr_eq(&x, &y));
+        assert_eq!(strong_count(&x), 2);
+
+        let empty: Cc<[u32]> = Cc::from_slice(&[]);
+        assert!(Cc::<[u32; 0]>::try_from(empty).is_ok());
+    }
+
+    #[test]
+    fn test_from_iter_empty() {
+        let x: Cc<[u64]> = (0..0).collect();
+        assert!(x.is_empty());
+
+        let y: Cc<[Box<u32>]> = None.into_iter().collect();
+        assert!(y.is_empty());
+    }
+
+    #[test]
+    fn test_from_empty_slice() {
+        let x: Cc<[u64]> = Cc::from_slice(&[]);
+        assert!(x.is_empty());
+        let w = x.downgrade();
+        drop(x);
+        assert!(w.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_from_str() {
+        let x = Cc::from_str("hi");
+        assert!(x == "hi");
+        assert!(x == *"hi");
+        assert!(x != "ho");
+        assert_eq!(format!("{}", x), "hi");
+
+        let y = x.clone();
+        assert!(Cc::ptr_eq(&x, &y));
+        assert!(!Cc::ptr_eq(&x, &Cc::from_str("hi")));
+    }
+
+    #[test]
+    fn test_from_empty_str() {
+        let x = Cc::from_str("");
+        assert_eq!(x.len(), 0);
+    }
+
+    #[test]
+    fn test_from() {
+        let x = Cc::from(5);
+        assert_eq!(*x, 5);
+        assert_eq!(strong_count(&x), 1);
+
+        let y: Cc<Box<u32>> = Box::new(6).into();
+        assert_eq!(**y, 6);
+    }
+
+    #[test]
+    fn test_color_new() {
+        let x = Cc::new(5);
+        assert!(x.color() == Color::Black);
+    }
+
+    #[test]
+    fn test_color_clone() {
+        let x = Cc::new(5);
+        x.metadata().color.set(Color::Purple);
+        let y = x.clone();
+        assert!(x.color() == Color::Black);
+        assert!(y.color() == Color::Black);
+    }
+
+    #[test]
+    fn test_color_upgrade() {
+        let x = Cc::new(5);
+        let w = x.downgrade();
+        x.metadata().color.set(Color::Gray);
+        let y = w.upgrade().unwrap();
+        assert!(y.color() == Color::Black);
+    }
+
+    #[test]
+    fn test_color_drop() {
+        let x = Cc::new(5);
+        let y = x.clone();
+        drop(y);
+        assert!(x.color() == Color::Purple);
+        collect_cycles();
+        assert!(x.color() == Color::Black);
+    }
+
+    #[test]
+    fn test_possible_root_buffered() {
+        let x = Cc::new(5);
+        let y = x.clone();
+        assert_eq!(collect::number_of_roots_buffered(), 0);
+        drop(y);
+        assert!(x.buffered());
+        assert_eq!(collect::number_of_roots_buffered(), 1);
+        collect_cycles();
+        assert!(!x.buffered());
+        assert_eq!(collect::number_of_roots_buffered(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "no-cycle-collection")]
+    fn test_no_cycle_collection() {
+        let x = Cc::new(5);
+        let y = x.clone();
+        drop(y);
+        assert!(!x.buffered());
+        assert!(x.color() != Color::Purple);
+        assert_eq!(collect::number_of_roots_buffered(), 0);
+        assert_eq!(collect_cycles(), CollectStats::default());
+        assert_eq!(*x, 5);
+    }
+
+    #[test]
+    fn test_possible_root_buffered_once() {
+        let x = Cc::new(5);
+        let y = x.clone();
+        let z = x.clone();
+        drop(y);
+        drop(z);
+        assert_eq!(collect::number_of_roots_buffered(), 1);
+
+        // Incrementing scrubs the purple mark, but the box is still in the
+        // buffer and must not be pushed again.
+        let w = x.clone();
+        assert!(x.color() == Color::Black);
+        drop(w);
+        assert!(x.color() == Color::Purple);
+        assert_eq!(collect::number_of_roots_buffered(), 1);
+
+        collect_cycles();
+        assert_eq!(collect::number_of_roots_buffered(), 0);
+    }
+
+    #[test]
+    fn test_buffered_root_freed() {
+        let x = Cc::new(5);
+        let w = x.downgrade();
+        let y = x.clone();
+        drop(y);
+        assert_eq!(collect::number_of_roots_buffered(), 1);
+        drop(x);
+        drop(w);
+        // the buffer keeps the allocation around until the next collection.
+        assert_eq!(collect::number_of_roots_buffered(), 1);
+        collect_cycles();
+        assert_eq!(collect::number_of_roots_buffered(), 0);
+    }
+
+    #[test]
+    fn test_buffered_root_unwrapped() {
+        let x = Cc::new(5);
+        let y = x.clone();
+        drop(y);
+        assert_eq!(collect::number_of_roots_buffered(), 1);
+        assert_eq!(super::try_unwrap(x), Ok(5));
+        assert_eq!(collect::number_of_roots_buffered(), 1);
+        collect_cycles();
+        assert_eq!(collect::number_of_roots_buffered(), 0);
+    }
+
+    thread_local!(static NODE_DROPS: Cell<usize> = Cell::new(0));
+
+    struct Node {
+        children: RefCell<Vec<Cc<Node>>>
+    }
+
+    impl Node {
+        fn new() -> Cc<Node> {
+            Cc::new(Node { children: RefCell::new(Vec::new()) })
+        }
+
+        fn add(&self, child: &Cc<Node>) {
+            self.children.borrow_mut().push(child.clone());
+        }
+    }
+
+    impl CcTrace for Node {
+        fn trace(&self, tracer: &mut Tracer) {
+            for child in self.children.borrow().iter() {
+                tracer(child);
+            }
+        }
+    }
+
+    impl Drop for Node {
+        fn drop(&mut self) {
+            NODE_DROPS.with(|d| d.set(d.get() + 1));
+        }
+    }
+
+    fn node_drops() -> usize {
+        NODE_DROPS.with(|d| d.get())
+    }
+
+    #[test]
+    fn test_collect_self_cycle() {
+        let a = Node::new();
+        a.add(&a);
+        let w = a.downgrade();
+        drop(a);
+        assert!(w.upgrade().is_some());
+        collect_cycles();
+        assert!(w.upgrade().is_none());
+        assert_eq!(node_drops(), 1);
+        assert_eq!(collect::number_of_roots_buffered(), 0);
+    }
+
+    #[test]
+    fn test_collect_cycle() {
+        let a = Node::new();
+        let b = Node::new();
+        a.add(&b);
+        b.add(&a);
+        let wa = a.downgrade();
+        let wb = b.downgrade();
+        drop(a);
+        drop(b);
+        collect_cycles();
+        assert!(wa.upgrade().is_none());
+        assert!(wb.upgrade().is_none());
+        assert_eq!(node_drops(), 2);
+    }
+
+    #[test]
+    fn test_collect_reentrant() {
+        thread_local!(static INNER_STATS: RefCell<Vec<CollectStats>> = RefCell::new(Vec::new()));
+
+        struct Reentrant {
+            other: RefCell<Option<Cc<Reentrant>>>,
+        }
+
+        impl CcTrace for Reentrant {
+            fn trace(&self, tracer: &mut Tracer) {
+                self.other.trace(tracer);
+            }
+        }
+
+        impl Drop for Reentrant {
+            fn drop(&mut self) {
+                // Make a new cycle while the collector is running, and try to
+                // collect it right away.
+                let c = Node::new();
+                c.add(&c);
+                drop(c);
+                let stats = collect_cycles();
+                INNER_STATS.with(|s| s.borrow_mut().push(stats));
+            }
+        }
+
+        let a = Cc::new(Reentrant { other: RefCell::new(None) });
+        let b = Cc::new(Reentrant { other: RefCell::new(Some(a.clone())) });
+        *a.other.borrow_mut() = Some(b.clone());
+        let wa = a.downgrade();
+        drop(a);
+        drop(b);
+
+        let stats = collect_cycles();
+        assert_eq!(stats.objects_freed, 2);
+        assert!(wa.upgrade().is_none());
+        INNER_STATS.with(|s| {
+            assert_eq!(*s.borrow(), vec![CollectStats::default(); 2]);
+        });
+
+        // The cycles made during the collection were left for the next one.
+        assert_eq!(node_drops(), 0);
+        let stats = collect_cycles();
+        assert_eq!(stats.objects_freed, 2);
+        assert_eq!(node_drops(), 2);
+    }
+
+    #[test]
+    fn test_try_unwrap_during_collection() {
+        thread_local!(static UNWRAPPED: Cell<(usize, usize)> = Cell::new((0, 0)));
+
+        struct Unwrapper {
+            other: RefCell<Option<Cc<Unwrapper>>>,
+        }
+
+        impl CcTrace for Unwrapper {
+            fn trace(&self, tracer: &mut Tracer) {
+                self.other.trace(tracer);
+            }
+        }
+
+        impl Drop for Unwrapper {
+            fn drop(&mut self) {
+                // The other box is only pointed to by this one, so it looks
+                // unique, but it belongs to the collector.
+                if let Some(other) = self.other.borrow_mut().take() {
+                    let result = super::try_unwrap(other);
+                    UNWRAPPED.with(|u| {
+                        let (ok, err) = u.get();
+                        u.set(if result.is_ok() { (ok + 1, err) } else { (ok, err + 1) });
+                    });
+                }
+            }
+        }
+
+        let a = Cc::new(Unwrapper { other: RefCell::new(None) });
+        let b = Cc::new(Unwrapper { other: RefCell::new(Some(a.clone())) });
+        *a.other.borrow_mut() = Some(b.clone());
+        drop(a);
+        drop(b);
+
+        let stats = collect_cycles();
+        assert_eq!(stats.objects_freed, 2);
+        assert_eq!(UNWRAPPED.with(|u| u.get()), (0, 2));
+    }
+
+    #[test]
+    fn test_collect_on_drop() {
+        let wa;
+        let wb;
+        {
+            let _guard = CollectOnDrop;
+            let a = Node::new();
+            let b = Node::new();
+            a.add(&b);
+            b.add(&a);
+            wa = a.downgrade();
+            wb = b.downgrade();
+            drop(a);
+            drop(b);
+            assert!(wa.upgrade().is_some());
+        }
+        assert!(wa.upgrade().is_none());
+        assert!(wb.upgrade().is_none());
+        assert_eq!(node_drops(), 2);
+    }
+
+    #[test]
+    fn test_collect_cycle_through_trait_objects() {
+        struct Dyn {
+            children: RefCell<Vec<Cc<CcTrace>>>,
+        }
+
+        impl CcTrace for Dyn {
+            fn trace(&self, tracer: &mut Tracer) {
+                self.children.trace(tracer);
+            }
+        }
+
+        // `b` and `c` point to each other through `Cc<CcTrace>`s, and `b`
+        // also owns a plain value and a `Node`.
+        let b = Cc::new(Dyn { children: RefCell::new(vec![Cc::new(5u32), Node::new()]) });
+        let c = Cc::new(Dyn { children: RefCell::new(vec![b.clone()]) });
+        b.children.borrow_mut().push(c.clone());
+        let wb = b.downgrade();
+        drop(b);
+        drop(c);
+        assert!(wb.upgrade().is_some());
+
+        collect_cycles();
+        assert!(wb.upgrade().is_none());
+        assert_eq!(node_drops(), 1);
+    }
+
+    #[test]
+    fn test_collect_cycle_with_outside_reference() {
+        let a = Node::new();
+        let b = Node::new();
+        a.add(&b);
+        b.add(&a);
+        drop(b);
+        collect_cycles();
+        assert_eq!(node_drops(), 0);
+        assert_eq!(strong_count(&a), 2);
+        assert!(a.color() == Color::Black);
+
+        // once the outside reference is gone, the cycle is garbage.
+        drop(a);
+        collect_cycles();
+        assert_eq!(node_drops(), 2);
+    }
+
+    #[test]
+    fn test_collect_cycle_pointing_at_live_value() {
+        let live = Node::new();
+        let a = Node::new();
+        let b = Node::new();
+        a.add(&b);
+        b.add(&a);
+        b.add(&live);
+        drop(a);
+        drop(b);
+        assert_eq!(strong_count(&live), 2);
+        collect_cycles();
+        assert_eq!(node_drops(), 2);
+        assert_eq!(strong_count(&live), 1);
+    }
+
+    #[test]
+    fn test_collect_stats() {
+        let a = Node::new();
+        let b = Node::new();
+        a.add(&b);
+        b.add(&a);
+        let live = Node::new();
+        drop(live.clone());
+        drop(a);
+        drop(b);
+
+        let stats = collect_cycles();
+        assert_eq!(stats, CollectStats { roots_scanned: 3, cycles_freed: 1, objects_freed: 2 });
+        assert_eq!(node_drops(), 2);
+
+        assert_eq!(collect_cycles(), CollectStats::default());
+    }
+
+    #[test]
+    fn test_buffered_root_dead_before_collection() {
+        disable_auto_collect();
+        let a = Node::new();
+        let b = Node::new();
+        a.add(&b);
+        let wa = a.downgrade();
+        drop(a.clone());
+        assert!(a.buffered());
+
+        // `a` dies by reference counting while it is still buffered, which
+        // drops its value and buffers `b` in turn.
+        drop(a);
+        assert_eq!(node_drops(), 1);
+        assert_eq!(strong_count(&b), 1);
+        assert_eq!(collect::number_of_roots_buffered(), 2);
+
+        let stats = collect_cycles();
+        assert_eq!(stats.roots_scanned, 2);
+        assert_eq!(stats.objects_freed, 0);
+        assert_eq!(node_drops(), 1);
+        assert!(wa.upgrade().is_none());
+        assert_eq!(collect::number_of_roots_buffered(), 0);
+
+        drop(wa);
+        drop(b);
+        assert_eq!(node_drops(), 2);
+    }
+
+    #[test]
+    fn test_collect_stats_several_cycles() {
+        for _ in 0..3 {
+            let a = Node::new();
+            a.add(&a);
+        }
+        let stats = collect_cycles();
+        assert_eq!(stats.roots_scanned, 3);
+        assert_eq!(stats.cycles_freed, 3);
+        assert_eq!(stats.objects_freed, 3);
+    }
+
+    #[test]
+    fn test_collect_with_budget() {
+        disable_auto_collect();
+        for _ in 0..10 {
+            let a = Node::new();
+            a.add(&a);
+        }
+        assert_eq!(collect::number_of_roots_buffered(), 10);
+
+        let stats = collect_cycles_with_budget(4);
+        assert_eq!(stats.roots_scanned, 4);
+        assert_eq!(stats.objects_freed, 4);
+        assert_eq!(node_drops(), 4);
+        assert_eq!(collect::number_of_roots_buffered(), 6);
+
+        let stats = collect_cycles_with_budget(100);
+        assert_eq!(stats.roots_scanned, 6);
+        assert_eq!(node_drops(), 10);
+        assert_eq!(collect::number_of_roots_buffered(), 0);
+    }
+
+    #[test]
+    fn test_collect_with_budget_shared_cycle() {
+        disable_auto_collect();
+        let live = Node::new();
+        let a = Node::new();
+        let b = Node::new();
+        a.add(&b);
+        b.add(&a);
+        b.add(&live);
+        drop(live.clone());
+        drop(a);
+        drop(b);
+        assert_eq!(collect::number_of_roots_buffered(), 3);
+
+        // `b` is still buffered, but is freed along with `a`. Freeing `b`
+        // buffers `live` again.
+        let stats = collect_cycles_with_budget(2);
+        assert_eq!(stats.objects_freed, 2);
+        assert_eq!(node_drops(), 2);
+        assert_eq!(strong_count(&live), 1);
+        assert_eq!(collect::number_of_roots_buffered(), 2);
+
+        let stats = collect_cycles_with_budget(2);
+        assert_eq!(stats.roots_scanned, 2);
+        assert_eq!(stats.objects_freed, 0);
+        assert_eq!(collect::number_of_roots_buffered(), 0);
+    }
+
+    #[test]
+    fn test_collect_from() {
+        disable_auto_collect();
+        let a = Node::new();
+        let b = Node::new();
+        a.add(&b);
+        b.add(&a);
+        let other = Node::new();
+        other.add(&other);
+        let wa = a.downgrade();
+        let wb = b.downgrade();
+        let wother = other.downgrade();
+        drop(a);
+        drop(b);
+        drop(other);
+        assert_eq!(collect::number_of_roots_buffered(), 3);
+
+        let stats = collect_from(&[&wa as &CcBoxPtr]);
+        assert_eq!(stats.roots_scanned, 1);
+        assert_eq!(stats.cycles_freed, 1);
+        assert_eq!(stats.objects_freed, 2);
+        assert_eq!(node_drops(), 2);
+        assert!(wa.upgrade().is_none());
+        assert!(wb.upgrade().is_none());
+        assert!(wother.upgrade().is_some());
 
-    // #[test]
-    // fn test_plugin() {
-    //     let x = CycleCollected {
-    //         a: Cc::new(5),
-    //         b: Cc::new("hello".into()),
-    //     };
+        // The freed boxes are still buffered, and draining the buffer only
+        // frees the cycle that wasn't collected from.
+        assert_eq!(collect::number_of_roots_buffered(), 3);
+        let stats = collect_cycles();
+        assert_eq!(stats.objects_freed, 1);
+        assert_eq!(node_drops(), 3);
+        assert_eq!(collect::number_of_roots_buffered(), 0);
+    }
 
-    //     CcTrace::trace(&x, &mut |v| {
-    //         println!("traced {:?}", v);
-    //     });
+    #[test]
+    fn test_is_being_collected() {
+        thread_local!(static SEEN: RefCell<Vec<bool>> = RefCell::new(Vec::new()));
 
-    //     assert!(false);
-    // }
+        struct Doomed(RefCell<Option<Cc<Doomed>>>);
 
-    // Tests copied from `Rc<T>`.
+        impl CcTrace for Doomed {
+            fn trace(&self, tracer: &mut Tracer) {
+                self.0.trace(tracer);
+            }
+        }
+
+        impl Drop for Doomed {
+            fn drop(&mut self) {
+                if let Some(ref next) = *self.0.borrow() {
+                    SEEN.with(|s| s.borrow_mut().push(Cc::is_being_collected(next)));
+                }
+            }
+        }
+
+        let a = Cc::new(Doomed(RefCell::new(None)));
+        let b = Cc::new(Doomed(RefCell::new(Some(a.clone()))));
+        *a.0.borrow_mut() = Some(b.clone());
+        assert!(!Cc::is_being_collected(&a));
+        assert!(!Cc::is_being_collected(&b));
+
+        let wa = a.downgrade();
+        drop(a);
+        drop(b);
+        assert!(!Cc::is_being_collected(&wa.upgrade().unwrap()));
+        let stats = collect_from(&[&wa as &CcBoxPtr]);
+        assert_eq!(stats.objects_freed, 2);
+        assert_eq!(SEEN.with(|s| s.borrow().clone()), vec![true, true]);
+
+        // Dropped by reference counting instead.
+        let c = Cc::new(Doomed(RefCell::new(None)));
+        drop(Cc::new(Doomed(RefCell::new(Some(c)))));
+        assert_eq!(SEEN.with(|s| s.borrow().clone()), vec![true, true, false]);
+    }
 
     #[test]
-    fn test_clone() {
-        let x = Cc::new(RefCell::new(5));
-        let y = x.clone();
-        *x.borrow_mut() = 20;
-        assert_eq!(*y.borrow(), 20);
+    fn test_collect_from_live() {
+        let a = Node::new();
+        a.add(&a);
+        let b = Node::new();
+        b.add(&a);
+        let wa = a.downgrade();
+        drop(a);
+
+        // `a` is owned by `b`, and `b` by us.
+        let stats = collect_from(&[&wa as &CcBoxPtr, &b as &CcBoxPtr]);
+        assert_eq!(stats.roots_scanned, 2);
+        assert_eq!(stats.objects_freed, 0);
+        assert_eq!(node_drops(), 0);
+        assert_eq!(strong_count(&wa.upgrade().unwrap()), 3);
+        assert_eq!(strong_count(&b), 1);
+
+        drop(b);
+        collect_cycles();
+        assert_eq!(node_drops(), 2);
     }
 
     #[test]
-    fn test_simple() {
-        let x = Cc::new(5);
-        assert_eq!(*x, 5);
+    fn test_weak_slot() {
+        struct Tree {
+            parent: WeakSlot<Tree>,
+            children: RefCell<Vec<Cc<Tree>>>,
+        }
+
+        impl CcTrace for Tree {
+            fn trace(&self, tracer: &mut Tracer) {
+                self.parent.trace(tracer);
+                self.children.trace(tracer);
+            }
+        }
+
+        impl Drop for Tree {
+            fn drop(&mut self) {
+                NODE_DROPS.with(|d| d.set(d.get() + 1));
+            }
+        }
+
+        let parent = Cc::new(Tree { parent: WeakSlot::new(), children: RefCell::new(Vec::new()) });
+        let child = Cc::new(Tree { parent: WeakSlot::new(), children: RefCell::new(Vec::new()) });
+        assert!(child.parent.get().is_none());
+        child.parent.set(&parent);
+        parent.children.borrow_mut().push(child.clone());
+        assert!(Cc::ptr_eq(&child.parent.get().unwrap(), &parent));
+        assert_eq!(strong_count(&parent), 1);
+        assert_eq!(weak_count(&parent), 1);
+
+        // The slot isn't an owning edge, so dropping the parent frees the
+        // whole tree without the collector's help.
+        drop(child);
+        drop(parent);
+        assert_eq!(node_drops(), 2);
+        assert_eq!(collect_cycles().objects_freed, 0);
+
+        let a = Cc::new(Tree { parent: WeakSlot::new(), children: RefCell::new(Vec::new()) });
+        a.parent.set(&a);
+        a.parent.clear();
+        assert!(a.parent.get().is_none());
+        assert_eq!(weak_count(&a), 0);
     }
 
     #[test]
-    fn test_simple_clone() {
-        let x = Cc::new(5);
-        let y = x.clone();
-        assert_eq!(*x, 5);
-        assert_eq!(*y, 5);
+    fn test_collect_rooted_cycle() {
+        let r = Node::new();
+        let a = Node::new();
+        r.add(&a);
+        a.add(&r);
+        r.root();
+        let wr = r.downgrade();
+        drop(a);
+        drop(r);
+
+        // The rooted box is live, and so is everything it points to.
+        let stats = collect_cycles();
+        assert_eq!(stats.objects_freed, 0);
+        assert_eq!(node_drops(), 0);
+
+        // Other garbage is still freed.
+        let b = Node::new();
+        b.add(&b);
+        drop(b);
+        collect_cycles();
+        assert_eq!(node_drops(), 1);
+
+        wr.upgrade().unwrap().unroot();
+        let stats = collect_cycles();
+        assert_eq!(stats.objects_freed, 2);
+        assert_eq!(node_drops(), 3);
+        assert!(wr.upgrade().is_none());
     }
 
     #[test]
-    fn test_destructor() {
-        let x: Cc<Box<_>> = Cc::new(Box::new(5));
-        assert_eq!(**x, 5);
+    fn test_collect_cycle_through_rooted() {
+        // The only path from `a` back to itself goes through the rooted box,
+        // so it survives even though it was the one that was buffered.
+        let r = Node::new();
+        let a = Node::new();
+        r.add(&a);
+        a.add(&r);
+        r.root();
+        drop(r);
+        let wa = a.downgrade();
+        drop(a);
+        collect_cycles();
+        assert_eq!(node_drops(), 0);
+        assert!(wa.upgrade().is_some());
+
+        let r = wa.upgrade().unwrap().children.borrow()[0].clone();
+        r.unroot();
+        drop(r);
+        collect_cycles();
+        assert_eq!(node_drops(), 2);
     }
 
     #[test]
-    fn test_live() {
-        let x = Cc::new(5);
-        let y = x.downgrade();
-        assert!(y.upgrade().is_some());
+    fn test_auto_collect() {
+        set_auto_collect_threshold(16);
+        for i in 0..1000 {
+            let a = Node::new();
+            let b = Node::new();
+            a.add(&b);
+            b.add(&a);
+            drop(a);
+            drop(b);
+
+            let live = 2 * (i + 1) - node_drops();
+            assert!(live <= 2 * 16);
+        }
+        collect_cycles();
+        assert_eq!(node_drops(), 2000);
     }
 
     #[test]
-    fn test_dead() {
-        let x = Cc::new(5);
-        let y = x.downgrade();
-        drop(x);
-        assert!(y.upgrade().is_none());
+    fn test_disable_auto_collect() {
+        disable_auto_collect();
+        for _ in 0..1000 {
+            let a = Node::new();
+            a.add(&a);
+        }
+        assert_eq!(node_drops(), 0);
+        assert_eq!(collect::number_of_roots_buffered(), 1000);
+        collect_cycles();
+        assert_eq!(node_drops(), 1000);
+    }
+
+    // Build a chain of nodes, each of which is buffered as a possible root
+    // and then incremented again, except for the first one.
+    fn buffered_chain(n: usize) -> Vec<Cc<Node>> {
+        let nodes: Vec<_> = (0..n).map(|_| Node::new()).collect();
+        for node in &nodes {
+            drop(node.clone());
+        }
+        for pair in nodes.windows(2) {
+            pair[0].add(&pair[1]);
+        }
+        nodes
     }
 
     #[test]
-    fn weak_self_cyclic() {
-        struct Cycle {
-            x: RefCell<Option<Weak<Cycle>>>
+    fn test_collection_paused() {
+        disable_auto_collect();
+        let nodes = buffered_chain(100);
+        assert_eq!(collect::number_of_roots_buffered(), 100);
+        drop(nodes);
+        collect_cycles();
+        assert_eq!(collect::number_of_roots_buffered(), 0);
+
+        let nodes = with_collection_paused(|| {
+            let nodes = buffered_chain(100);
+            assert_eq!(collect::number_of_roots_buffered(), 0);
+            nodes
+        });
+        assert_eq!(collect::number_of_roots_buffered(), 1);
+        drop(nodes);
+        collect_cycles();
+        assert_eq!(node_drops(), 200);
+    }
+
+    #[test]
+    fn test_collection_paused_auto_collect() {
+        set_auto_collect_threshold(0);
+        let w = with_collection_paused(|| {
+            let a = Node::new();
+            let b = Node::new();
+            a.add(&b);
+            b.add(&a);
+            let w = a.downgrade();
+            drop(a);
+            drop(b);
+            with_collection_paused(|| {
+                let c = Node::new();
+                c.add(&c);
+            });
+            assert!(w.upgrade().is_some());
+            assert_eq!(node_drops(), 0);
+            w
+        });
+        assert!(w.upgrade().is_none());
+        assert_eq!(node_drops(), 3);
+        assert_eq!(collect::number_of_roots_buffered(), 0);
+    }
+
+    #[test]
+    fn test_drop_long_chain() {
+        struct Link {
+            next: Option<Cc<Link>>,
         }
 
-        let a = Cc::new(Cycle { x: RefCell::new(None) });
-        let b = a.clone().downgrade();
-        *a.x.borrow_mut() = Some(b);
+        impl CcTrace for Link {
+            fn trace(&self, tracer: &mut Tracer) {
+                self.next.trace(tracer);
+            }
+        }
 
-        // hopefully we don't double-free (or leak)...
+        let mut head = None;
+        for _ in 0..100000 {
+            head = Some(Cc::new(Link { next: head }));
+        }
+        let tail = {
+            let mut link = head.as_ref().unwrap();
+            while let Some(ref next) = link.next {
+                link = next;
+            }
+            link.downgrade()
+        };
+
+        drop(head);
+        assert!(tail.upgrade().is_none());
     }
 
     #[test]
-    fn is_unique() {
-        let x = Cc::new(3);
-        assert!(super::is_unique(&x));
-        let y = x.clone();
-        assert!(!super::is_unique(&x));
-        drop(y);
-        assert!(super::is_unique(&x));
-        let w = x.downgrade();
-        assert!(!super::is_unique(&x));
-        drop(w);
-        assert!(super::is_unique(&x));
+    fn test_collect_long_cycle() {
+        struct Link {
+            next: RefCell<Option<Cc<Link>>>,
+        }
+
+        impl CcTrace for Link {
+            fn trace(&self, tracer: &mut Tracer) {
+                self.next.trace(tracer);
+            }
+        }
+
+        let last = Cc::new(Link { next: RefCell::new(None) });
+        let mut head = last.clone();
+        for _ in 1..200000 {
+            head = Cc::new(Link { next: RefCell::new(Some(head)) });
+        }
+        *last.next.borrow_mut() = Some(head.clone());
+        let tail = last.downgrade();
+        drop(last);
+
+        // Live while `head` is around, which every phase has to find out by
+        // walking the whole cycle.
+        assert_eq!(collect_cycles().objects_freed, 0);
+        assert!(tail.upgrade().is_some());
+
+        drop(head);
+        assert_eq!(collect_cycles().objects_freed, 200000);
+        assert!(tail.upgrade().is_none());
     }
 
     #[test]
-    fn test_strong_count() {
-        let a = Cc::new(0u32);
-        assert!(strong_count(&a) == 1);
-        let w = a.downgrade();
-        assert!(strong_count(&a) == 1);
-        let b = w.upgrade().expect("upgrade of live rc failed");
-        assert!(strong_count(&b) == 2);
-        assert!(strong_count(&a) == 2);
-        drop(w);
+    fn test_dump_roots() {
+        let a = Node::new();
+        let b = Node::new();
+        a.add(&b);
+        b.add(&a);
+        let wa = a.downgrade();
+        let addr_a = format!("{:p}", a);
+        let addr_b = format!("{:p}", b);
+        assert_eq!(dump_roots(), vec![]);
+
         drop(a);
-        assert!(strong_count(&b) == 1);
-        let c = b.clone();
-        assert!(strong_count(&b) == 2);
-        assert!(strong_count(&c) == 2);
+        drop(b);
+        let roots = dump_roots();
+        assert_eq!(roots.len(), 2);
+        assert_eq!(format!("{:#x}", roots[0].address), addr_a);
+        assert_eq!(format!("{:#x}", roots[1].address), addr_b);
+        assert_eq!(roots[0], RootInfo { address: roots[0].address, strong: 1, weak: 1,
+                                       color: Color::Purple });
+        assert_eq!(roots[1], RootInfo { address: roots[1].address, strong: 1, weak: 0,
+                                       color: Color::Purple });
+
+        // Dumping doesn't change anything.
+        assert_eq!(dump_roots(), roots);
+        collect_cycles();
+        assert_eq!(dump_roots(), vec![]);
+        assert!(wa.upgrade().is_none());
     }
 
     #[test]
-    fn test_weak_count() {
-        let a = Cc::new(0u32);
-        assert!(strong_count(&a) == 1);
-        assert!(weak_count(&a) == 0);
-        let w = a.downgrade();
-        assert!(strong_count(&a) == 1);
-        assert!(weak_count(&a) == 1);
-        drop(w);
-        assert!(strong_count(&a) == 1);
-        assert!(weak_count(&a) == 0);
-        let c = a.clone();
-        assert!(strong_count(&a) == 2);
-        assert!(weak_count(&a) == 0);
-        drop(c);
+    fn test_trace_children() {
+        let parent = Node::new();
+        let children: Vec<Cc<Node>> = (0..3).map(|_| Node::new()).collect();
+        for child in &children {
+            parent.add(child);
+        }
+
+        let addresses = trace_children(&*parent);
+        assert_eq!(addresses.len(), 3);
+        for (addr, child) in addresses.iter().zip(&children) {
+            assert_eq!(format!("{:#x}", addr), format!("{:p}", *child));
+        }
+        assert!(trace_children(&*children[0]).is_empty());
+        assert_eq!(strong_count(&children[0]), 2);
     }
 
     #[test]
-    fn try_unwrap() {
-        let x = Cc::new(3);
-        assert_eq!(super::try_unwrap(x), Ok(3));
-        let x = Cc::new(4);
-        let _y = x.clone();
-        assert_eq!(super::try_unwrap(x), Err(Cc::new(4)));
-        let x = Cc::new(5);
-        let _w = x.downgrade();
-        assert_eq!(super::try_unwrap(x), Err(Cc::new(5)));
+    fn test_dump_graph_dot() {
+        let a = Node::new();
+        let b = Node::new();
+        a.add(&b);
+        b.add(&a);
+        let addr_a = format!("{:p}", a);
+        let addr_b = format!("{:p}", b);
+
+        let dot = dump_graph_dot(&a);
+        assert!(dot.starts_with("digraph cc {\n"));
+        assert!(dot.ends_with("}\n"));
+
+        let mut edges: Vec<(String, String)> = dot.lines()
+            .filter(|l| l.contains("->"))
+            .map(|l| {
+                let mut ends = l.trim().trim_end_matches(';').split(" -> ")
+                    .map(|end| end.trim_matches('"').to_string());
+                (ends.next().unwrap(), ends.next().unwrap())
+            })
+            .collect();
+        edges.sort();
+        let mut expected = vec![(addr_a.clone(), addr_b.clone()), (addr_b.clone(), addr_a.clone())];
+        expected.sort();
+        assert_eq!(edges, expected);
+
+        let nodes = dot.lines().filter(|l| l.contains("[label=")).count();
+        assert_eq!(nodes, 2);
+        assert!(dot.contains(&format!("\"{}\" [label=\"{}\\nstrong: 2, weak: 0\"];", addr_a, addr_a)));
+
+        drop(a);
+        drop(b);
+        collect_cycles();
+    }
+
+    #[derive(Clone)]
+    struct CountingAllocator {
+        live: Rc<Cell<isize>>,
+    }
+
+    unsafe impl Allocator for CountingAllocator {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            self.live.set(self.live.get() + 1);
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            self.live.set(self.live.get() - 1);
+            Global.deallocate(ptr, layout)
+        }
     }
 
     #[test]
-    fn get_mut() {
-        let mut x = Cc::new(3);
-        *super::get_mut(&mut x).unwrap() = 4;
-        assert_eq!(*x, 4);
-        let y = x.clone();
-        assert!(super::get_mut(&mut x).is_none());
+    fn test_new_in() {
+        let alloc = CountingAllocator { live: Rc::new(Cell::new(0)) };
+        let live = alloc.live.clone();
+
+        let x = Cc::new_in(Box::new(5), alloc.clone());
+        let y = Cc::new_in(vec![1, 2, 3], alloc.clone());
+        assert_eq!(live.get(), 2);
+        assert_eq!(**x, 5);
+        assert_eq!(*y, vec![1, 2, 3]);
+        assert!(Rc::ptr_eq(&Cc::allocator(&x).live, &live));
+
+        let z = x.clone();
+        let w = x.downgrade();
+        drop(x);
+        drop(z);
+        assert_eq!(live.get(), 2);
+        assert!(w.upgrade().is_none());
+        drop(w);
+        // Dropping `x` left the box buffered as a possible root, so the
+        // collector is the one to free it.
+        assert_eq!(live.get(), 2);
+        collect_cycles();
+        assert_eq!(live.get(), 1);
         drop(y);
-        assert!(super::get_mut(&mut x).is_some());
-        let _w = x.downgrade();
-        assert!(super::get_mut(&mut x).is_none());
+        assert_eq!(live.get(), 0);
     }
 
     #[test]
-    fn test_cowrc_clone_make_unique() {
-        let mut cow0 = Cc::new(75);
-        let mut cow1 = cow0.clone();
-        let mut cow2 = cow1.clone();
-
-        assert!(75 == *cow0.make_unique());
-        assert!(75 == *cow1.make_unique());
-        assert!(75 == *cow2.make_unique());
+    fn test_new_in_collect_cycle() {
+        struct CountedNode {
+            children: RefCell<Vec<Cc<CountedNode, CountingAllocator>>>,
+        }
 
-        *cow0.make_unique() += 1;
-        *cow1.make_unique() += 2;
-        *cow2.make_unique() += 3;
+        impl CcTrace for CountedNode {
+            fn trace(&self, tracer: &mut Tracer) {
+                self.children.trace(tracer);
+            }
+        }
 
-        assert!(76 == *cow0);
-        assert!(77 == *cow1);
-        assert!(78 == *cow2);
+        let alloc = CountingAllocator { live: Rc::new(Cell::new(0)) };
+        let live = alloc.live.clone();
+        let a = Cc::new_in(CountedNode { children: RefCell::new(Vec::new()) }, alloc.clone());
+        let b = Cc::new_in(CountedNode { children: RefCell::new(Vec::new()) }, alloc);
+        a.children.borrow_mut().push(b.clone());
+        b.children.borrow_mut().push(a.clone());
+        drop(a);
+        drop(b);
+        assert_eq!(live.get(), 2);
 
-        // none should point to the same backing memory
-        assert!(*cow0 != *cow1);
-        assert!(*cow0 != *cow2);
-        assert!(*cow1 != *cow2);
+        let stats = collect_cycles();
+        assert_eq!(stats.objects_freed, 2);
+        assert_eq!(live.get(), 0);
     }
 
     #[test]
-    fn test_cowrc_clone_unique2() {
-        let mut cow0 = Cc::new(75);
-        let cow1 = cow0.clone();
-        let cow2 = cow1.clone();
+    fn test_drop_panics() {
+        struct PanicOnDrop(Box<u32>);
 
-        assert!(75 == *cow0);
-        assert!(75 == *cow1);
-        assert!(75 == *cow2);
+        impl CcTrace for PanicOnDrop {
+            fn trace(&self, _tracer: &mut Tracer) { }
+        }
 
-        *cow0.make_unique() += 1;
+        impl Drop for PanicOnDrop {
+            fn drop(&mut self) {
+                panic!("PanicOnDrop dropped");
+            }
+        }
 
-        assert!(76 == *cow0);
-        assert!(75 == *cow1);
-        assert!(75 == *cow2);
+        let alloc = CountingAllocator { live: Rc::new(Cell::new(0)) };
+        let live = alloc.live.clone();
 
-        // cow1 and cow2 should share the same contents
-        // cow0 should have a unique reference
-        assert!(*cow0 != *cow1);
-        assert!(*cow0 != *cow2);
-        assert!(*cow1 == *cow2);
+        let x = Cc::new_in(PanicOnDrop(Box::new(5)), alloc.clone());
+        assert!(panic::catch_unwind(AssertUnwindSafe(move || drop(x))).is_err());
+        assert_eq!(live.get(), 0);
+
+        // The boxes that die along with the one whose destructor panicked are
+        // still dropped, and freed, while unwinding.
+        struct DropCounter(Rc<Cell<usize>>);
+
+        impl CcTrace for DropCounter {
+            fn trace(&self, _tracer: &mut Tracer) { }
+        }
+
+        impl Drop for DropCounter {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+
+        struct PanicWithChildren(Vec<Cc<Vec<Cc<DropCounter, CountingAllocator>>, CountingAllocator>>);
+
+        impl CcTrace for PanicWithChildren {
+            fn trace(&self, tracer: &mut Tracer) {
+                self.0.trace(tracer);
+            }
+        }
+
+        impl Drop for PanicWithChildren {
+            fn drop(&mut self) {
+                panic!("PanicWithChildren dropped");
+            }
+        }
+
+        let dropped = Rc::new(Cell::new(0));
+        let children = (0..3).map(|_| {
+            let grandchild = Cc::new_in(DropCounter(dropped.clone()), alloc.clone());
+            Cc::new_in(vec![grandchild], alloc.clone())
+        }).collect();
+        let z = Cc::new_in(PanicWithChildren(children), alloc.clone());
+        assert!(panic::catch_unwind(AssertUnwindSafe(move || drop(z))).is_err());
+        assert_eq!(dropped.get(), 3);
+        assert_eq!(live.get(), 0);
+
+        // A weak reference keeps the allocation around, dead, until it goes.
+        let y = Cc::new_in(PanicOnDrop(Box::new(6)), alloc);
+        let w = y.downgrade();
+        assert!(panic::catch_unwind(AssertUnwindSafe(move || drop(y))).is_err());
+        assert_eq!(live.get(), 1);
+        assert!(w.upgrade().is_none());
+        assert_eq!(w.weak_count(), 1);
+        drop(w);
+        assert_eq!(live.get(), 0);
+
+        // Dropping values still works afterwards.
+        let a = Node::new();
+        a.add(&Node::new());
+        drop(a);
+        assert_eq!(node_drops(), 2);
     }
 
     #[test]
-    fn test_cowrc_clone_weak() {
-        let mut cow0 = Cc::new(75);
-        let cow1_weak = cow0.downgrade();
+    fn test_clone_dead_weak() {
+        let alloc = CountingAllocator { live: Rc::new(Cell::new(0)) };
+        let live = alloc.live.clone();
 
-        assert!(75 == *cow0);
-        assert!(75 == *cow1_weak.upgrade().unwrap());
+        let x = Cc::new_in(Box::new(5), alloc);
+        let w = x.downgrade();
+        drop(x);
+        assert_eq!(live.get(), 1);
+        assert_eq!(w.strong_count(), 0);
 
-        *cow0.make_unique() += 1;
+        let clones: Vec<_> = (0..5).map(|_| w.clone()).collect();
+        assert_eq!(w.weak_count(), 6);
+        for c in &clones {
+            assert!(c.upgrade().is_none());
+            assert!(Weak::ptr_eq(c, &w));
+        }
+        let again = clones[0].clone();
+        assert!(again.upgrade().is_none());
+        assert_eq!(w.weak_count(), 7);
 
-        assert!(76 == *cow0);
-        assert!(cow1_weak.upgrade().is_none());
+        drop(clones);
+        drop(w);
+        assert_eq!(live.get(), 1);
+        drop(again);
+        assert_eq!(live.get(), 0);
     }
 
     #[test]
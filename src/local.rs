@@ -0,0 +1,33 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Stand-in for `thread_local!` state when building without `std`.
+
+/// A value in a static that is only ever accessed from one thread.
+///
+/// Without `std` there are no thread locals, so this is shared by the whole
+/// program, rather than there being one per thread.
+pub struct Local<T>(T);
+
+// Nothing here makes this sound: the crate can only be built without `std`
+// with the `unsafe-single-threaded` feature, which promises that it is only
+// ever used from one thread, with no interrupt handler using it either.
+unsafe impl<T> Sync for Local<T> {}
+
+impl<T> Local<T> {
+    pub const fn new(value: T) -> Local<T> {
+        Local(value)
+    }
+
+    /// Like `LocalKey::with`.
+    pub fn with<F, R>(&'static self, f: F) -> R where F: FnOnce(&T) -> R {
+        f(&self.0)
+    }
+}
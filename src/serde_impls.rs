@@ -0,0 +1,39 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `serde` support for `Cc<T>`, enabled with the `serde` feature.
+//!
+//! A `Cc<T>` serializes as the `T` it points to, and deserializes by
+//! putting a freshly deserialized `T` in a new `Cc`. Sharing is not
+//! preserved: every `Cc` is serialized independently, so two `Cc`s pointing to
+//! the same box come back as two separate boxes. Serializing a cycle of `Cc`s
+//! never finishes: it recurses around the cycle until the stack overflows.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::{Cc, CcTrace};
+
+/// Serializes the value the `Cc<T>` points to, as if it were not shared.
+///
+/// Every `Cc` is serialized on its own, so a value reachable through several
+/// of them is serialized once for each. A cycle of `Cc`s is not detected:
+/// serializing it recurses until the stack overflows, which aborts the
+/// program rather than returning an error.
+impl<T: 'static + CcTrace + Serialize + ?Sized> Serialize for Cc<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (**self).serialize(serializer)
+    }
+}
+
+impl<'de, T: 'static + CcTrace + Deserialize<'de>> Deserialize<'de> for Cc<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Cc<T>, D::Error> {
+        T::deserialize(deserializer).map(Cc::new)
+    }
+}
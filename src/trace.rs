@@ -0,0 +1,577 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Tracing the edges between `CcBox`es, for the collector.
+
+use core::any::{Any, TypeId};
+use core::alloc::Allocator;
+use core::cell::{Cell, RefCell};
+use core::mem::MaybeUninit;
+
+use alloc::boxed::Box;
+use alloc::arc::Arc;
+use alloc::rc::Rc;
+use collections::borrow::{Cow, ToOwned};
+use collections::btree_map::BTreeMap;
+use collections::string::String;
+use collections::vec::Vec;
+use collections::vec_deque::VecDeque;
+
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+
+use super::{Cc, CcBoxPtr, CcProjection, Weak, WeakSlot};
+
+/// A callback that is given each box a traced value owns.
+pub type Tracer<'a> = FnMut(&CcBoxPtr) + 'a;
+
+/// A value that can tell the collector which `Cc` boxes it owns.
+///
+/// This trait is object safe, so a graph can hold children of different types
+/// as `Cc<CcTrace>`, and they are traced through their own impls.
+pub trait CcTrace {
+    /// Call `tracer` with each `Cc` that this value owns, directly or through
+    /// the values it contains.
+    ///
+    /// A collection traces the same value several times, and this must report
+    /// the same children every time it is called during one. A value that
+    /// changes what it owns from within `trace`, through interior
+    /// mutability, breaks that.
+    fn trace(&self, tracer: &mut Tracer);
+}
+
+// The trace functions registered with `register_trace_fn`, by the type they
+// trace.
+cc_thread_local!(static TRACE_FNS: RefCell<BTreeMap<TypeId, Rc<Fn(&Any, &mut Tracer)>>> =
+                 RefCell::new(BTreeMap::new()));
+
+/// Register `f` as the way to trace values of type `T` on this thread, when
+/// they are behind an `Any` trait object.
+///
+/// This is an escape hatch for types that can't implement `CcTrace`, like
+/// types from other crates that hold `Cc`s: keep them as `Cc<Box<Any>>`, and
+/// the collector traces them with `f`. Registering another function for the
+/// same type replaces the first one. Values of a type with no registered
+/// function are traced as owning nothing, which keeps what they own alive.
+///
+/// # Examples
+///
+/// ```
+/// use std::any::Any;
+/// use bacon_rajan_cc::{register_trace_fn, Cc};
+///
+/// // Stands in for a type from another crate.
+/// struct Foreign {
+///     child: Cc<u32>,
+/// }
+///
+/// register_trace_fn::<Foreign, _>(|value, tracer| tracer(&value.child));
+///
+/// let node: Cc<Box<Any>> = Cc::new(Box::new(Foreign { child: Cc::new(5) }));
+/// ```
+pub fn register_trace_fn<T: Any, F: 'static + Fn(&T, &mut Tracer)>(f: F) {
+    let f = move |value: &Any, tracer: &mut Tracer| {
+        if let Some(value) = value.downcast_ref::<T>() {
+            f(value, tracer);
+        }
+    };
+    TRACE_FNS.with(|fns| fns.borrow_mut().insert(TypeId::of::<T>(), Rc::new(f)));
+}
+
+impl CcTrace for Any {
+    /// Traces the value with the function registered for its type by
+    /// `register_trace_fn`, if any.
+    #[inline]
+    fn trace(&self, tracer: &mut Tracer) {
+        // Let go of the registry before calling the function, which might
+        // register more.
+        let f = TRACE_FNS.with(|fns| fns.borrow().get(&self.type_id()).cloned());
+        if let Some(f) = f {
+            f(self, tracer);
+        }
+    }
+}
+
+macro_rules! cc_trace_leaf {
+    ($($t:ty),*) => {
+        $(
+            impl CcTrace for $t {
+                #[inline(always)]
+                fn trace(&self, _tracer: &mut Tracer) { }
+            }
+        )*
+    }
+}
+
+cc_trace_leaf!((), bool, char, f32, f64,
+               i8, i16, i32, i64, isize,
+               u8, u16, u32, u64, usize,
+               str, String);
+
+impl<T: CcTrace + ?Sized> CcTrace for Box<T> {
+    #[inline(always)]
+    fn trace(&self, tracer: &mut Tracer) {
+        (**self).trace(tracer);
+    }
+}
+
+impl<T: CcTrace> CcTrace for Option<T> {
+    #[inline]
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Some(ref x) = *self {
+            x.trace(tracer);
+        }
+    }
+}
+
+impl<T: CcTrace, E: CcTrace> CcTrace for Result<T, E> {
+    #[inline]
+    fn trace(&self, tracer: &mut Tracer) {
+        match *self {
+            Ok(ref x) => x.trace(tracer),
+            Err(ref e) => e.trace(tracer),
+        }
+    }
+}
+
+impl<'a, T: ToOwned + ?Sized> CcTrace for Cow<'a, T> where T::Owned: CcTrace {
+    /// Only an owned value is traced. A borrowed one is no more owned than
+    /// through a plain reference.
+    #[inline]
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Cow::Owned(ref x) = *self {
+            x.trace(tracer);
+        }
+    }
+}
+
+impl<'a, T: ?Sized> CcTrace for &'a T {
+    /// A reference doesn't own what it points to, so there is nothing to
+    /// report.
+    #[inline(always)]
+    fn trace(&self, _tracer: &mut Tracer) { }
+}
+
+impl<T> CcTrace for MaybeUninit<T> {
+    /// There is no telling whether the value has been written yet, so it
+    /// can't be traced. Anything it owns is kept alive until the value is
+    /// known to be initialized.
+    #[inline(always)]
+    fn trace(&self, _tracer: &mut Tracer) { }
+}
+
+impl<T: CcTrace> CcTrace for [T] {
+    #[inline]
+    fn trace(&self, tracer: &mut Tracer) {
+        for x in self.iter() {
+            x.trace(tracer);
+        }
+    }
+}
+
+impl<T: CcTrace> CcTrace for Vec<T> {
+    #[inline]
+    fn trace(&self, tracer: &mut Tracer) {
+        (**self).trace(tracer);
+    }
+}
+
+impl<T: CcTrace> CcTrace for VecDeque<T> {
+    #[inline]
+    fn trace(&self, tracer: &mut Tracer) {
+        for x in self.iter() {
+            x.trace(tracer);
+        }
+    }
+}
+
+impl<T: 'static + CcTrace + ?Sized, A: 'static + Allocator> CcTrace for Cc<T, A> {
+    /// A `Cc` owns the box it points to, so tracing it reports that box, and
+    /// not the boxes owned by its value.
+    #[inline(always)]
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer(self);
+    }
+}
+
+impl<T: 'static + CcTrace + ?Sized, U: ?Sized, A: 'static + Allocator> CcTrace for CcProjection<T, U, A> {
+    /// A projection owns the box the value it is a part of is in.
+    #[inline(always)]
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer(CcProjection::owner(self));
+    }
+}
+
+impl<T: 'static + CcTrace + ?Sized, A: 'static + Allocator> CcTrace for Weak<T, A> {
+    /// A `Weak` doesn't own the box it points to, so there is nothing to
+    /// report.
+    #[inline(always)]
+    fn trace(&self, _tracer: &mut Tracer) { }
+}
+
+impl<T: ?Sized> CcTrace for Rc<T> {
+    /// The collector doesn't manage `Rc`s, so it doesn't look inside them.
+    /// Any `Cc`s the value owns count as owned from outside of the graph,
+    /// and are kept alive for as long as the `Rc` is.
+    #[inline(always)]
+    fn trace(&self, _tracer: &mut Tracer) { }
+}
+
+impl<T: ?Sized> CcTrace for Arc<T> {
+    /// As for `Rc`, the value is treated as outside of the graph.
+    #[inline(always)]
+    fn trace(&self, _tracer: &mut Tracer) { }
+}
+
+impl<T: 'static + CcTrace + ?Sized, A: 'static + Allocator> CcTrace for WeakSlot<T, A> {
+    /// Like a `Weak`, the slot doesn't own the box it points to.
+    #[inline(always)]
+    fn trace(&self, _tracer: &mut Tracer) { }
+}
+
+impl<T: CcTrace, const N: usize> CcTrace for [T; N] {
+    #[inline]
+    fn trace(&self, tracer: &mut Tracer) {
+        (self as &[T]).trace(tracer);
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K: CcTrace, V: CcTrace, S> CcTrace for HashMap<K, V, S> {
+    #[inline]
+    fn trace(&self, tracer: &mut Tracer) {
+        for (k, v) in self.iter() {
+            k.trace(tracer);
+            v.trace(tracer);
+        }
+    }
+}
+
+impl<K: CcTrace, V: CcTrace> CcTrace for BTreeMap<K, V> {
+    #[inline]
+    fn trace(&self, tracer: &mut Tracer) {
+        for (k, v) in self.iter() {
+            k.trace(tracer);
+            v.trace(tracer);
+        }
+    }
+}
+
+macro_rules! cc_trace_tuple {
+    ($(($($name:ident),+)),*) => {
+        $(
+            impl<$($name: CcTrace),+> CcTrace for ($($name,)+) {
+                #[inline]
+                #[allow(non_snake_case)]
+                fn trace(&self, tracer: &mut Tracer) {
+                    let ($(ref $name,)+) = *self;
+                    $($name.trace(tracer);)+
+                }
+            }
+        )*
+    }
+}
+
+cc_trace_tuple!((A),
+                (A, B),
+                (A, B, C),
+                (A, B, C, D),
+                (A, B, C, D, E),
+                (A, B, C, D, E, F),
+                (A, B, C, D, E, F, G),
+                (A, B, C, D, E, F, G, H),
+                (A, B, C, D, E, F, G, H, I),
+                (A, B, C, D, E, F, G, H, I, J),
+                (A, B, C, D, E, F, G, H, I, J, K),
+                (A, B, C, D, E, F, G, H, I, J, K, L));
+
+impl<T: Copy + CcTrace> CcTrace for Cell<T> {
+    /// Trace a copy of the value in the cell.
+    ///
+    /// Neither `Cc` nor `Weak` is `Copy`, so a cell holding one of them has
+    /// to be a `RefCell` to be traced.
+    #[inline]
+    fn trace(&self, tracer: &mut Tracer) {
+        self.get().trace(tracer);
+    }
+}
+
+impl<T: CcTrace + ?Sized> CcTrace for RefCell<T> {
+    /// Trace the value in the cell, unless it is mutably borrowed.
+    ///
+    /// A mutably borrowed cell is in use, so the box it is in is live, and
+    /// treating the value as if it owned no boxes can only keep more boxes
+    /// alive. Nothing else runs while the collector is tracing, so the cell
+    /// stays borrowed for the whole collection, and it is consistently skipped.
+    #[inline]
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Ok(value) = self.try_borrow() {
+            value.trace(tracer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{register_trace_fn, CcTrace, Tracer};
+    use std::any::Any;
+    use std::borrow::Cow;
+    use std::cell::{Cell, RefCell};
+    use std::collections::{BTreeMap, HashMap, VecDeque};
+    use std::convert::TryFrom;
+    use std::rc::Rc;
+    use std::sync::Arc;
+    use {Cc, Weak};
+
+    // A value owning a single `Cc`.
+    struct Edge(Cc<u32>);
+
+    impl CcTrace for Edge {
+        fn trace(&self, tracer: &mut Tracer) {
+            tracer(&self.0);
+        }
+    }
+
+    fn edge() -> Edge {
+        Edge(Cc::new(0))
+    }
+
+    fn count_children<T: CcTrace + ?Sized>(value: &T) -> usize {
+        let mut children = 0;
+        value.trace(&mut |_| children += 1);
+        children
+    }
+
+    #[test]
+    fn test_trace_leaves() {
+        assert_eq!(count_children(&5u32), 0);
+        assert_eq!(count_children(&true), 0);
+        assert_eq!(count_children(&String::from("hello")), 0);
+        assert_eq!(count_children("hello"), 0);
+    }
+
+    #[test]
+    fn test_trace_vec() {
+        let v = vec![edge(), edge(), edge()];
+        assert_eq!(count_children(&v), 3);
+        assert_eq!(count_children(&v[1..]), 2);
+        assert_eq!(count_children(&Vec::<Edge>::new()), 0);
+    }
+
+    #[test]
+    fn test_trace_vec_deque() {
+        let mut queue = VecDeque::new();
+        queue.push_back(Cc::new(1u32));
+        queue.push_back(Cc::new(2));
+        queue.push_front(Cc::new(0));
+        assert_eq!(count_children(&queue), 3);
+        queue.pop_front();
+        assert_eq!(count_children(&queue), 2);
+        assert_eq!(count_children(&VecDeque::<Cc<u32>>::new()), 0);
+    }
+
+    #[test]
+    fn test_trace_option() {
+        assert_eq!(count_children(&Some(edge())), 1);
+        assert_eq!(count_children(&None::<Edge>), 0);
+    }
+
+    #[test]
+    fn test_trace_result() {
+        let ok: Result<Cc<u32>, Cc<String>> = Ok(Cc::new(5));
+        let err: Result<Cc<u32>, Cc<String>> = Err(Cc::new("oops".to_string()));
+        assert_eq!(count_children(&ok), 1);
+        assert_eq!(count_children(&err), 1);
+        assert_eq!(count_children(&Ok::<u32, Edge>(5)), 0);
+        assert_eq!(count_children(&Err::<Edge, u32>(5)), 0);
+    }
+
+    #[test]
+    fn test_trace_cow() {
+        let children = vec![Cc::new(1u32), Cc::new(2)];
+        let borrowed: Cow<[Cc<u32>]> = Cow::Borrowed(&children);
+        assert_eq!(count_children(&borrowed), 0);
+        let owned: Cow<[Cc<u32>]> = Cow::Owned(children.clone());
+        assert_eq!(count_children(&owned), 2);
+    }
+
+    #[test]
+    fn test_trace_registered_fn() {
+        // Stands in for a foreign type that can't implement `CcTrace`.
+        struct Foreign {
+            next: RefCell<Option<Cc<Box<Any>>>>,
+        }
+
+        thread_local!(static CALLS: Cell<usize> = Cell::new(0));
+
+        let unregistered: Box<Any> = Box::new(Foreign { next: RefCell::new(None) });
+        assert_eq!(count_children(&unregistered), 0);
+
+        register_trace_fn::<Foreign, _>(|value, tracer| {
+            CALLS.with(|c| c.set(c.get() + 1));
+            if let Some(ref next) = *value.next.borrow() {
+                tracer(next);
+            }
+        });
+        assert_eq!(count_children(&unregistered), 0);
+        assert_eq!(CALLS.with(|c| c.get()), 1);
+
+        // A cycle through the foreign type is collected.
+        let node: Cc<Box<Any>> = Cc::new(Box::new(Foreign { next: RefCell::new(None) }));
+        *node.downcast_ref::<Foreign>().unwrap().next.borrow_mut() = Some(node.clone());
+        assert_eq!(count_children(&**node), 1);
+        let weak = node.downgrade();
+        drop(node);
+        CALLS.with(|c| c.set(0));
+        assert_eq!(::collect_cycles().objects_freed, 1);
+        assert!(CALLS.with(|c| c.get()) > 0);
+        assert!(weak.upgrade().is_none());
+
+        // Only values of the registered type are traced with the function.
+        let other: Box<Any> = Box::new(edge());
+        assert_eq!(count_children(&other), 0);
+    }
+
+    #[test]
+    fn test_trace_rc_arc() {
+        assert_eq!(count_children(&Rc::new(edge())), 0);
+        assert_eq!(count_children(&Arc::new(5u32)), 0);
+    }
+
+    #[test]
+    fn test_trace_ref() {
+        let e = edge();
+        assert_eq!(count_children(&&e), 0);
+    }
+
+    #[test]
+    fn test_trace_hash_map() {
+        let mut map = HashMap::new();
+        map.insert(1u32, Cc::new(1u32));
+        map.insert(2, Cc::new(2));
+        map.insert(3, Cc::new(3));
+        assert_eq!(count_children(&map), 3);
+        assert_eq!(count_children(&HashMap::<u32, Cc<u32>>::new()), 0);
+
+        let mut by_key = HashMap::new();
+        by_key.insert(Cc::new(0u32), edge());
+        assert_eq!(count_children(&by_key), 2);
+    }
+
+    #[test]
+    fn test_trace_btree_map() {
+        let mut map = BTreeMap::new();
+        map.insert(1u32, edge());
+        map.insert(2, edge());
+        assert_eq!(count_children(&map), 2);
+    }
+
+    #[test]
+    fn test_trace_tuple() {
+        assert_eq!(count_children(&(Cc::new(5u32), Cc::new(String::from("five")))), 2);
+        assert_eq!(count_children(&(edge(),)), 1);
+        assert_eq!(count_children(&(1u32, edge(), Some(edge()), None::<Edge>)), 2);
+        assert_eq!(count_children(&(edge(), edge(), edge(), edge(), edge(), edge(),
+                                    edge(), edge(), edge(), edge(), edge(), edge())), 12);
+    }
+
+    #[test]
+    fn test_trace_cell() {
+        assert_eq!(count_children(&Cell::new(5u32)), 0);
+        assert_eq!(count_children(&Cell::new((1u8, 'a'))), 0);
+    }
+
+    #[test]
+    fn test_trace_trait_objects() {
+        let children: Vec<Cc<CcTrace>> = vec![Cc::new(5u32), Cc::new(edge()),
+                                             Cc::new(vec![edge(), edge()])];
+        assert_eq!(count_children(&children), 3);
+
+        let mut grandchildren = 0;
+        for child in &children {
+            grandchildren += count_children(&**child);
+        }
+        assert_eq!(grandchildren, 3);
+    }
+
+    #[test]
+    fn test_trace_box() {
+        assert_eq!(count_children(&Box::new(edge())), 1);
+    }
+
+    #[test]
+    fn test_trace_array() {
+        assert_eq!(count_children(&[edge(), edge()]), 2);
+        assert_eq!(count_children(&[Some(edge()), None, Some(edge())]), 2);
+
+        let quad = [Cc::new(0u32), Cc::new(1), Cc::new(2), Cc::new(3)];
+        assert_eq!(count_children(&quad), 4);
+        assert_eq!(count_children(&[0u32; 0]), 0);
+
+        // Any length works, not just small ones.
+        let many: Vec<Cc<u32>> = (0..100).map(Cc::new).collect();
+        let big = <[Cc<u32>; 100]>::try_from(many).unwrap();
+        assert_eq!(count_children(&big), 100);
+    }
+
+    #[test]
+    fn test_trace_cc_and_weak() {
+        struct Parent {
+            child: Cc<u32>,
+            parent: Weak<u32>,
+        }
+
+        impl CcTrace for Parent {
+            fn trace(&self, tracer: &mut Tracer) {
+                self.child.trace(tracer);
+                self.parent.trace(tracer);
+            }
+        }
+
+        let child = Cc::new(1);
+        let parent = Cc::new(2);
+        let p = Parent { child: child.clone(), parent: parent.downgrade() };
+
+        let mut strong = Vec::new();
+        p.trace(&mut |c| strong.push(c.strong()));
+        assert_eq!(strong, vec![2]);
+
+        assert_eq!(count_children(&vec![child.clone(), parent.clone()]), 2);
+        assert_eq!(count_children(&Some(parent.downgrade())), 0);
+    }
+
+    #[test]
+    fn test_trace_ref_cell() {
+        let cell = RefCell::new(Cc::new(5u32));
+        assert_eq!(count_children(&cell), 1);
+
+        {
+            let _borrowed = cell.borrow();
+            assert_eq!(count_children(&cell), 1);
+        }
+
+        {
+            let _borrowed = cell.borrow_mut();
+            assert_eq!(count_children(&cell), 0);
+        }
+
+        assert_eq!(count_children(&RefCell::new(vec![edge(), edge()])), 2);
+    }
+
+    #[test]
+    fn test_trace_visits_each_child() {
+        let a = Edge(Cc::new(1));
+        let b = Edge(Cc::new(2));
+        let v = vec![a, b];
+        let mut strong = Vec::new();
+        v.trace(&mut |child| strong.push(child.strong()));
+        assert_eq!(strong, vec![1, 1]);
+    }
+}
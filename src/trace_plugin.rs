@@ -21,14 +21,18 @@
 
 
 use rustc::plugin::Registry;
-use syntax::ast::{Expr, MetaItem, Mutability};
+use syntax::ast::{Attribute, Expr, Ident, Item_, MetaItem, Mutability, StructDef, Ty, Ty_,
+                  TyParamBound, VariantKind};
+use syntax::attr::AttrMetaMethods;
 use syntax::ext::base::{Annotatable, ExtCtxt, MultiDecorator};
 use syntax::ext::build::AstBuilder;
 use syntax::ext::deriving::generic::{combine_substructure, EnumMatching, FieldInfo, MethodDef,
                                      Struct, Substructure, TraitDef, ty};
 use syntax::codemap::Span;
+use syntax::owned_slice::OwnedSlice;
 use syntax::parse::token::intern;
 use syntax::ptr::P;
+use syntax::visit::{self, Visitor};
 
 pub fn expand_derive_cc_trace(cx: &mut ExtCtxt,
                               span: Span,
@@ -36,11 +40,14 @@ pub fn expand_derive_cc_trace(cx: &mut ExtCtxt,
                               item: Annotatable,
                               push: &mut FnMut(Annotatable))
 {
-    println!("FITZGEN HELLO");
+    // The deriving framework copies the item's lifetime and type parameters
+    // onto the generated impl and bounds every type parameter by the trait
+    // path below, so `struct Node<T> { .. }` expands to
+    // `impl<T: ::bacon_rajan_cc::CcTrace> ::bacon_rajan_cc::CcTrace for Node<T>`.
     let cc_trace_trait_def = TraitDef {
         span: span,
         attributes: Vec::new(),
-        path: ty::Path::new(vec!("bacon_rajan_cc", "Trace")),
+        path: ty::Path::new(vec!("bacon_rajan_cc", "CcTrace")),
         additional_bounds: Vec::new(),
         generics: ty::LifetimeBounds::empty(),
         methods: vec!(
@@ -61,7 +68,129 @@ pub fn expand_derive_cc_trace(cx: &mut ExtCtxt,
         associated_types: Vec::new(),
     };
 
-    cc_trace_trait_def.expand(cx, mitem, &item, push);
+    // A type parameter that only shows up in fields marked `#[cc(skip)]`, such
+    // as the `T` of a `PhantomData<T>`, is never traced, so it doesn't need
+    // the bound.
+    let untraced = untraced_ty_params(&item);
+    cc_trace_trait_def.expand(cx, mitem, &item, &mut |generated| {
+        push(remove_cc_trace_bounds(generated, &untraced))
+    });
+}
+
+// Whether a field is marked `#[cc(skip)]`, which leaves it out of the generated
+// `trace`. This is for fields that hold `Cc`s which are not owned by the value,
+// like caches, as far as the collector is concerned. The compiler doesn't know
+// the attribute, so crates using it need `#![feature(custom_attribute)]`.
+// Fields of tuple variants can't have attributes, and are always traced.
+fn is_skipped(attrs: &[Attribute]) -> bool {
+    attrs.iter().any(|attr| {
+        attr.check_name("cc") && match attr.meta_item_list() {
+            Some(items) => items.iter().any(|item| item.check_name("skip")),
+            None => false,
+        }
+    })
+}
+
+// The type parameters of the item that don't appear in the type of any field
+// that is traced.
+fn untraced_ty_params(item: &Annotatable) -> Vec<Ident> {
+    fn traced_struct_fields(def: &StructDef, uses: &mut TyParamUses) {
+        for field in def.fields.iter() {
+            if !is_skipped(&field.node.attrs) {
+                uses.visit_ty(&field.node.ty);
+            }
+        }
+    }
+
+    let item = match *item {
+        Annotatable::Item(ref item) => item,
+        _ => return Vec::new(),
+    };
+    let (generics, mut uses) = match item.node {
+        Item_::ItemStruct(_, ref generics) | Item_::ItemEnum(_, ref generics) => {
+            let params = generics.ty_params.iter().map(|p| p.ident).collect::<Vec<_>>();
+            let used = vec![false; params.len()];
+            (generics, TyParamUses { params: params, used: used })
+        }
+        _ => return Vec::new(),
+    };
+    match item.node {
+        Item_::ItemStruct(ref def, _) => traced_struct_fields(def, &mut uses),
+        Item_::ItemEnum(ref def, _) => {
+            for variant in def.variants.iter() {
+                match variant.node.kind {
+                    VariantKind::TupleVariantKind(ref args) => {
+                        for arg in args.iter() {
+                            uses.visit_ty(&arg.ty);
+                        }
+                    }
+                    VariantKind::StructVariantKind(ref def) => traced_struct_fields(def, &mut uses),
+                }
+            }
+        }
+        _ => {}
+    }
+
+    generics.ty_params.iter().zip(uses.used.iter())
+        .filter(|&(_, &used)| !used)
+        .map(|(p, _)| p.ident)
+        .collect()
+}
+
+// Finds which of `params` are named in the types it visits.
+struct TyParamUses {
+    params: Vec<Ident>,
+    used: Vec<bool>,
+}
+
+impl<'v> Visitor<'v> for TyParamUses {
+    fn visit_ty(&mut self, ty: &'v Ty) {
+        // `T`, or a path starting with it, like `T::Item`.
+        if let Ty_::TyPath(None, ref path) = ty.node {
+            if let Some(i) = self.params.iter().position(|p| *p == path.segments[0].identifier) {
+                self.used[i] = true;
+            }
+        }
+        visit::walk_ty(self, ty);
+    }
+}
+
+// Take the `::bacon_rajan_cc::CcTrace` bound the deriving framework put on
+// each of `params` back off of the generated impl.
+fn remove_cc_trace_bounds(generated: Annotatable, params: &[Ident]) -> Annotatable {
+    if params.is_empty() {
+        return generated;
+    }
+
+    let is_cc_trace = |bound: &TyParamBound| match *bound {
+        TyParamBound::TraitTyParamBound(ref poly, _) => {
+            let path = &poly.trait_ref.path;
+            path.global && path.segments.len() == 2
+                && &path.segments[0].identifier.name.as_str()[..] == "bacon_rajan_cc"
+                && &path.segments[1].identifier.name.as_str()[..] == "CcTrace"
+        }
+        _ => false,
+    };
+
+    match generated {
+        Annotatable::Item(item) => Annotatable::Item(item.map(|mut item| {
+            if let Item_::ItemImpl(_, _, ref mut generics, _, _, _) = item.node {
+                generics.ty_params = generics.ty_params.map(|p| {
+                    let mut p = p.clone();
+                    if params.contains(&p.ident) {
+                        let bounds = p.bounds.iter()
+                            .filter(|b| !is_cc_trace(b))
+                            .cloned()
+                            .collect();
+                        p.bounds = OwnedSlice::from_vec(bounds);
+                    }
+                    p
+                });
+            }
+            item
+        })),
+        other => other,
+    }
 }
 
 fn cc_trace_substructure(cx: &mut ExtCtxt, trait_span: Span, substr: &Substructure) -> P<Expr> {
@@ -86,13 +215,20 @@ fn cc_trace_substructure(cx: &mut ExtCtxt, trait_span: Span, substr: &Substructu
     };
     let mut stmts = Vec::new();
 
+    // Structs trace each of their fields, which for a tuple struct are
+    // `self.0`, `self.1`, and so on, and a unit struct has none to trace.
+    // Enums are expanded into a `match` over every variant, with each arm
+    // tracing the fields of its variant; unit variants have no fields and so
+    // trace nothing.
     let fields = match *substr.fields {
         Struct(ref fs) | EnumMatching(_, _, ref fs) => fs,
-        _ => cx.span_bug(trait_span, "impossible substructure in `jstraceable`")
+        _ => cx.span_bug(trait_span, "impossible substructure in `derive(CcTrace)`")
     };
 
-    for &FieldInfo { ref self_, span, .. } in fields.iter() {
-        stmts.push(call_cc_trace(span, self_.clone()));
+    for &FieldInfo { ref self_, span, attrs, .. } in fields.iter() {
+        if !is_skipped(attrs) {
+            stmts.push(call_cc_trace(span, self_.clone()));
+        }
     }
 
     cx.expr_block(cx.block(trait_span, stmts, None))
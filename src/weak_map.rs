@@ -0,0 +1,161 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Maps that don't keep their values alive.
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use super::{Cc, CcTrace, Tracer, Weak};
+
+/// A `HashMap` from keys to `Weak<V>`s, for caches whose entries go away once
+/// nothing else uses their values.
+///
+/// An entry whose value is gone is removed when it is looked up, or by
+/// `prune`. Until then, it still counts towards `len`, and keeps the key and
+/// the value's allocation around, but not the value itself.
+///
+/// # Examples
+///
+/// ```
+/// use bacon_rajan_cc::Cc;
+/// use bacon_rajan_cc::weak_map::WeakValueHashMap;
+///
+/// let mut cache = WeakValueHashMap::new();
+/// let five = Cc::new(5);
+/// cache.insert("five", &five);
+///
+/// assert_eq!(cache.get("five"), Some(five.clone()));
+/// drop(five);
+/// assert_eq!(cache.get("five"), None);
+/// assert!(cache.is_empty());
+/// ```
+pub struct WeakValueHashMap<K, V: 'static + CcTrace> {
+    map: HashMap<K, Weak<V>>,
+}
+
+impl<K: Eq + Hash, V: 'static + CcTrace> WeakValueHashMap<K, V> {
+    /// Makes an empty map.
+    pub fn new() -> WeakValueHashMap<K, V> {
+        WeakValueHashMap { map: HashMap::new() }
+    }
+
+    /// Maps `key` to a weak reference to `value`, and returns the value it
+    /// was mapped to before, if that is still alive.
+    pub fn insert(&mut self, key: K, value: &Cc<V>) -> Option<Cc<V>> {
+        self.map.insert(key, value.downgrade()).and_then(|old| old.upgrade())
+    }
+
+    /// Returns the value `key` is mapped to, if it is still alive. If it
+    /// isn't, the entry is removed.
+    pub fn get<Q: ?Sized>(&mut self, key: &Q) -> Option<Cc<V>> where K: Borrow<Q>, Q: Eq + Hash {
+        let value = match self.map.get(key) {
+            Some(weak) => weak.upgrade(),
+            None => return None,
+        };
+        if value.is_none() {
+            self.map.remove(key);
+        }
+        value
+    }
+
+    /// Removes the entry for `key`, and returns its value if that is still
+    /// alive.
+    pub fn remove<Q: ?Sized>(&mut self, key: &Q) -> Option<Cc<V>> where K: Borrow<Q>, Q: Eq + Hash {
+        self.map.remove(key).and_then(|weak| weak.upgrade())
+    }
+
+    /// Removes every entry whose value is gone.
+    pub fn prune(&mut self) {
+        self.map.retain(|_, weak| !weak.is_expired());
+    }
+
+    /// Returns the number of entries, including the ones whose values are
+    /// gone but that weren't removed yet.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns true if there are no entries, not even ones whose values are
+    /// gone.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+impl<K: Eq + Hash, V: 'static + CcTrace> Default for WeakValueHashMap<K, V> {
+    fn default() -> WeakValueHashMap<K, V> {
+        WeakValueHashMap::new()
+    }
+}
+
+impl<K: CcTrace, V: 'static + CcTrace> CcTrace for WeakValueHashMap<K, V> {
+    /// Only the keys are owned by the map.
+    #[inline]
+    fn trace(&self, tracer: &mut Tracer) {
+        for key in self.map.keys() {
+            key.trace(tracer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WeakValueHashMap;
+    use {collect_cycles, Cc};
+
+    #[test]
+    fn test_weak_value_hash_map() {
+        let mut cache = WeakValueHashMap::new();
+        let a = Cc::new(String::from("a"));
+        let b = Cc::new(String::from("b"));
+        assert!(cache.insert(1, &a).is_none());
+        assert!(cache.insert(2, &b).is_none());
+        assert_eq!(cache.len(), 2);
+
+        // Referenced values survive, and stay shared.
+        let got = cache.get(&1).unwrap();
+        assert!(Cc::ptr_eq(&got, &a));
+        drop(got);
+        collect_cycles();
+        assert!(Cc::ptr_eq(&cache.get(&1).unwrap(), &a));
+
+        // Dead entries are removed by looking them up.
+        drop(b);
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(&2).is_none());
+        assert_eq!(cache.len(), 1);
+        assert!(cache.get(&3).is_none());
+
+        let replaced = cache.insert(1, &Cc::new(String::from("new a")));
+        assert!(Cc::ptr_eq(&replaced.unwrap(), &a));
+        assert!(cache.get(&1).is_none());
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_weak_value_hash_map_prune() {
+        let mut cache = WeakValueHashMap::new();
+        let values: Vec<Cc<u32>> = (0..10).map(Cc::new).collect();
+        for (i, v) in values.iter().enumerate() {
+            cache.insert(i, v);
+        }
+
+        let kept: Vec<Cc<u32>> = values.into_iter().filter(|v| **v % 2 == 0).collect();
+        assert_eq!(cache.len(), 10);
+        cache.prune();
+        assert_eq!(cache.len(), 5);
+        for v in &kept {
+            assert!(Cc::ptr_eq(&cache.remove(&(**v as usize)).unwrap(), v));
+        }
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,29 @@
+#![cfg(feature = "serde")]
+
+extern crate bacon_rajan_cc;
+extern crate serde_json;
+
+use bacon_rajan_cc::Cc;
+
+#[test]
+fn test_serde_round_trip() {
+    let x = Cc::new(vec![1u32, 2, 3]);
+    let json = serde_json::to_string(&x).unwrap();
+    assert_eq!(json, "[1,2,3]");
+
+    let y: Cc<Vec<u32>> = serde_json::from_str(&json).unwrap();
+    assert_eq!(*x, *y);
+    assert!(!Cc::ptr_eq(&x, &y));
+}
+
+#[test]
+fn test_serde_does_not_preserve_sharing() {
+    let x = Cc::new(vec![1u32]);
+    let pair = vec![x.clone(), x.clone()];
+    let json = serde_json::to_string(&pair).unwrap();
+    assert_eq!(json, "[[1],[1]]");
+
+    let pair: Vec<Cc<Vec<u32>>> = serde_json::from_str(&json).unwrap();
+    assert_eq!(*pair[0], *pair[1]);
+    assert!(!Cc::ptr_eq(&pair[0], &pair[1]));
+}
@@ -1,8 +1,10 @@
-#![feature(custom_derive, trace_macros, plugin)]
+#![feature(custom_attribute, custom_derive, trace_macros, plugin)]
 #![plugin(bacon_rajan_cc)]
 
 extern crate bacon_rajan_cc;
 use bacon_rajan_cc::*;
+use std::cell::RefCell;
+use std::rc::Rc;
 
 trace_macros!(true);
 
@@ -21,9 +23,147 @@ fn test_plugin() {
         b: Cc::new("hello".into()),
     };
 
+    let mut traced = 0;
     CcTrace::trace(&x, &mut |v| {
-        println!("traced {:?}", v);
+        assert_eq!(v.strong(), 1);
+        traced += 1;
     });
 
-    assert!(false);
+    assert_eq!(traced, 2);
+}
+
+// A value owning a single `Cc`, traced by hand.
+#[derive(Debug)]
+struct Edge(Cc<u32>);
+
+impl CcTrace for Edge {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer(&self.0);
+    }
+}
+
+fn edge() -> Edge {
+    Edge(Cc::new(0))
+}
+
+#[derive(CcTrace, Debug)]
+enum Expr {
+    Nil,
+    Leaf(Edge),
+    Pair(Edge, Edge),
+    Node { lhs: Edge, op: u8, rhs: Option<Edge> },
+}
+
+fn count_children<T: CcTrace>(value: &T) -> usize {
+    let mut children = 0;
+    value.trace(&mut |_| children += 1);
+    children
+}
+
+#[test]
+fn test_plugin_enum() {
+    assert_eq!(count_children(&Expr::Nil), 0);
+    assert_eq!(count_children(&Expr::Leaf(edge())), 1);
+    assert_eq!(count_children(&Expr::Pair(edge(), edge())), 2);
+    assert_eq!(count_children(&Expr::Node { lhs: edge(), op: 0, rhs: None }), 1);
+    assert_eq!(count_children(&Expr::Node { lhs: edge(), op: 0, rhs: Some(edge()) }), 2);
+}
+
+#[derive(CcTrace, Debug)]
+struct Tagged<T> {
+    child: Edge,
+    data: T,
+}
+
+#[derive(CcTrace, Debug)]
+struct Pair<A, B>(A, B);
+
+#[test]
+fn test_plugin_generic() {
+    assert_eq!(count_children(&Tagged { child: edge(), data: 5u32 }), 1);
+    assert_eq!(count_children(&Tagged { child: edge(), data: edge() }), 2);
+    assert_eq!(count_children(&Tagged { child: edge(), data: vec![edge(), edge()] }), 3);
+    assert_eq!(count_children(&Pair(edge(), Tagged { child: edge(), data: () })), 2);
+}
+
+// Not `CcTrace` itself.
+#[derive(Debug)]
+struct Opaque;
+
+#[derive(CcTrace, Debug)]
+struct WithCache<T> {
+    child: Edge,
+    #[cc(skip)]
+    cache: Option<Cc<u32>>,
+    #[cc(skip)]
+    marker: ::std::marker::PhantomData<T>,
+}
+
+#[derive(CcTrace, Debug)]
+enum MaybeCached {
+    Cached { value: Edge, #[cc(skip)] cache: Cc<u32> },
+}
+
+#[test]
+fn test_plugin_skip() {
+    let cache = Cc::new(5);
+    let x: WithCache<Opaque> = WithCache {
+        child: edge(),
+        cache: Some(cache.clone()),
+        marker: ::std::marker::PhantomData,
+    };
+    assert_eq!(count_children(&x), 1);
+
+    assert_eq!(count_children(&MaybeCached::Cached { value: edge(), cache: cache }), 1);
+}
+
+#[derive(CcTrace)]
+struct Mixed {
+    children: Vec<Cc<CcTrace>>,
+    first: Cc<CcTrace>,
+}
+
+#[test]
+fn test_plugin_trait_objects() {
+    let x = Mixed {
+        children: vec![Cc::new(5u32), Cc::new(edge())],
+        first: Cc::new(String::from("first")),
+    };
+    assert_eq!(count_children(&x), 3);
+}
+
+#[derive(CcTrace, Debug)]
+struct Wrapper(Cc<u32>);
+
+#[derive(CcTrace, Debug)]
+struct Marker;
+
+#[derive(CcTrace)]
+struct Link(RefCell<Option<Cc<Link>>>);
+
+#[test]
+fn test_plugin_tuple_and_unit_structs() {
+    assert_eq!(count_children(&Wrapper(Cc::new(5))), 1);
+    assert_eq!(count_children(&Marker), 0);
+
+    let link = Cc::new(Link(RefCell::new(None)));
+    *link.0.borrow_mut() = Some(link.clone());
+    assert_eq!(count_children(&*link), 1);
+    let weak = link.downgrade();
+    drop(link);
+    collect_cycles();
+    assert!(weak.upgrade().is_none());
+}
+
+#[derive(CcTrace)]
+struct WithRc {
+    shared: Rc<u32>,
+    child: Cc<u32>,
+}
+
+#[test]
+fn test_plugin_rc() {
+    let x = WithRc { shared: Rc::new(5), child: Cc::new(6) };
+    assert_eq!(count_children(&x), 1);
+    assert_eq!(*x.shared, 5);
 }